@@ -0,0 +1,377 @@
+//! `#[derive(OxidbRecord)]` for the Oxidb crate, which re-exports it.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::punctuated::Punctuated;
+use syn::{Data, DeriveInput, Error, Fields, GenericArgument, Ident, Lit, LitStr, Meta, PathArguments, Token, Type};
+
+/// Implements `OxidbRecord` for a struct with named fields.
+///
+/// On the struct:
+/// - `#[oxidb(table = "users")]`: the table name; defaults to the struct name
+///   in snake_case.
+/// - `#[oxidb(id_strategy = "random")]`: `auto_increment` (the default),
+///   `random` or `time_ordered`.
+/// - `#[oxidb(allow_extra_fields)]`: records may carry fields the struct does
+///   not have.
+/// - `#[oxidb(unique(first, last))]`: a unique constraint over several fields.
+///
+/// On a field:
+/// - `#[oxidb(id)]`: the `u64` field holding the record id; defaults to the
+///   field named `id`.
+/// - `#[oxidb(index)]` or `#[oxidb(index = "hash")]`: a secondary index,
+///   ordered unless `hash` is given.
+/// - `#[oxidb(unique)]`: a unique constraint on this field alone.
+/// - `#[oxidb(type = "timestamp")]`: the column type, when the one derived
+///   from the Rust type is not right.
+/// - `#[oxidb(nullable)]` and `#[oxidb(default = 0)]`: as on `Column`.
+///
+/// Column types follow the Rust types: integers are `int`, `Option<T>` is a
+/// nullable column of `T`, sequences are `list`, maps are `map` and anything
+/// else, such as nested structs and enums, is `any`. `#[serde(rename)]` and
+/// `#[serde(skip)]` on fields and `#[serde(rename_all)]` on the struct are
+/// honoured.
+#[proc_macro_derive(OxidbRecord, attributes(oxidb))]
+pub fn derive_oxidb_record(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    expand(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+#[derive(Default)]
+struct FieldAttrs {
+    id: bool,
+    index: Option<TokenStream2>,
+    unique: bool,
+}
+
+struct Column {
+    name: String,
+    ty: TokenStream2,
+    nullable: bool,
+    default: Option<Lit>,
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(Error::new_spanned(input, "OxidbRecord needs a struct with named fields")),
+        },
+        _ => return Err(Error::new_spanned(input, "OxidbRecord can only be derived for structs")),
+    };
+
+    let mut table = snake_case(&input.ident.to_string());
+    let rename_all = rename_rule(input)?;
+    let mut id_strategy = quote!(::Oxidb::IdStrategy::AutoIncrement);
+    let mut allow_extra_fields = false;
+    let mut composite_unique: Vec<Vec<Ident>> = Vec::new();
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("oxidb")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("table") {
+                table = meta.value()?.parse::<LitStr>()?.value();
+            } else if meta.path.is_ident("id_strategy") {
+                let value: LitStr = meta.value()?.parse()?;
+                id_strategy = match value.value().as_str() {
+                    "auto_increment" => quote!(::Oxidb::IdStrategy::AutoIncrement),
+                    "random" => quote!(::Oxidb::IdStrategy::Random),
+                    "time_ordered" => quote!(::Oxidb::IdStrategy::TimeOrdered),
+                    _ => return Err(Error::new_spanned(value, "expected auto_increment, random or time_ordered")),
+                };
+            } else if meta.path.is_ident("allow_extra_fields") {
+                allow_extra_fields = true;
+            } else if meta.path.is_ident("unique") {
+                let mut fields = Vec::new();
+                meta.parse_nested_meta(|field| {
+                    fields.push(field.path.require_ident()?.clone());
+                    Ok(())
+                })?;
+                composite_unique.push(fields);
+            } else {
+                return Err(meta.error("unknown oxidb attribute"));
+            }
+            Ok(())
+        })?;
+    }
+
+    let mut parsed = Vec::new();
+    for field in fields {
+        let ident = field.ident.clone().expect("named field");
+        let (name, skipped) = serde_name(field, rename_all)?;
+        let (mut ty, mut nullable) = column_type(&field.ty);
+        let mut default = None;
+        let mut attrs = FieldAttrs::default();
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("oxidb")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("id") {
+                    attrs.id = true;
+                } else if meta.path.is_ident("index") {
+                    attrs.index = Some(if meta.input.peek(Token![=]) {
+                        let value: LitStr = meta.value()?.parse()?;
+                        match value.value().as_str() {
+                            "hash" => quote!(::Oxidb::IndexKind::Hash),
+                            "ordered" => quote!(::Oxidb::IndexKind::Ordered),
+                            _ => return Err(Error::new_spanned(value, "expected hash or ordered")),
+                        }
+                    } else {
+                        quote!(::Oxidb::IndexKind::Ordered)
+                    });
+                } else if meta.path.is_ident("unique") {
+                    attrs.unique = true;
+                } else if meta.path.is_ident("type") {
+                    let value: LitStr = meta.value()?.parse()?;
+                    ty = match value.value().as_str() {
+                        "any" => quote!(::Oxidb::ColumnType::Any),
+                        "bool" => quote!(::Oxidb::ColumnType::Bool),
+                        "int" => quote!(::Oxidb::ColumnType::Int),
+                        "float" => quote!(::Oxidb::ColumnType::Float),
+                        "string" => quote!(::Oxidb::ColumnType::String),
+                        "bytes" => quote!(::Oxidb::ColumnType::Bytes),
+                        "timestamp" => quote!(::Oxidb::ColumnType::Timestamp),
+                        "list" => quote!(::Oxidb::ColumnType::List),
+                        "map" => quote!(::Oxidb::ColumnType::Map),
+                        _ => return Err(Error::new_spanned(value, "unknown column type")),
+                    };
+                } else if meta.path.is_ident("nullable") {
+                    nullable = true;
+                } else if meta.path.is_ident("default") {
+                    default = Some(meta.value()?.parse::<Lit>()?);
+                } else {
+                    return Err(meta.error("unknown oxidb attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        parsed.push((ident, skipped, Column { name, ty, nullable, default }, attrs, &field.ty));
+    }
+
+    let explicit: Vec<_> = parsed.iter().filter(|(_, _, _, attrs, _)| attrs.id).collect();
+    if explicit.len() > 1 {
+        return Err(Error::new_spanned(&explicit[1].0, "only one field can be #[oxidb(id)]"));
+    }
+    let id_index = parsed.iter()
+        .position(|(_, _, _, attrs, _)| attrs.id)
+        .or_else(|| parsed.iter().position(|(ident, ..)| ident == "id"))
+        .ok_or_else(|| Error::new_spanned(input, "OxidbRecord needs an `id` field or a field marked #[oxidb(id)]"))?;
+    let (id_ident, _, id_column, _, id_ty) = parsed.remove(id_index);
+    if !is_u64(id_ty) {
+        return Err(Error::new_spanned(id_ty, "the id field must be a u64"));
+    }
+    let id_name = id_column.name;
+
+    let mut columns = Vec::new();
+    // Rust field name to column name, for resolving `unique(...)` on the struct.
+    let mut names = Vec::new();
+    let mut indexes = Vec::new();
+    let mut unique = Vec::new();
+    for (ident, _, column, attrs, _) in parsed.into_iter().filter(|(_, skipped, ..)| !skipped) {
+        if let Some(kind) = attrs.index {
+            indexes.push((column.name.clone(), kind));
+        }
+        if attrs.unique {
+            unique.push(vec![column.name.clone()]);
+        }
+        names.push((ident, column.name.clone()));
+        columns.push(column);
+    }
+
+    for fields in composite_unique {
+        let resolved = fields.iter()
+            .map(|field| {
+                names.iter()
+                    .find(|(ident, _)| ident == field)
+                    .map(|(_, name)| name.clone())
+                    .ok_or_else(|| Error::new_spanned(field, "no such column"))
+            })
+            .collect::<syn::Result<Vec<_>>>()?;
+        unique.push(resolved);
+    }
+
+    let columns = columns.iter().map(|Column { name, ty, nullable, default }| {
+        let nullable = nullable.then(|| quote!(.nullable()));
+        let default = default.as_ref().map(|lit| quote!(.default(#lit)));
+        quote!(::Oxidb::Column::new(#name, #ty) #nullable #default)
+    });
+    let indexes = indexes.iter().map(|(field, kind)| {
+        quote!(::Oxidb::IndexDef { field: #field.to_string(), kind: #kind })
+    });
+    let unique = unique.iter().map(|fields| {
+        quote!(::Oxidb::UniqueConstraint { fields: vec![#(#fields.to_string()),*] })
+    });
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::Oxidb::OxidbRecord for #ident #ty_generics #where_clause {
+            const ID_FIELD: &'static str = #id_name;
+            const TABLE: &'static str = #table;
+            const ID_STRATEGY: ::Oxidb::IdStrategy = #id_strategy;
+
+            fn id(&self) -> u64 {
+                self.#id_ident
+            }
+
+            fn schema() -> ::std::option::Option<::Oxidb::Schema> {
+                ::std::option::Option::Some(::Oxidb::Schema {
+                    columns: vec![#(#columns),*],
+                    allow_extra_fields: #allow_extra_fields,
+                })
+            }
+
+            fn indexes() -> ::std::vec::Vec<::Oxidb::IndexDef> {
+                vec![#(#indexes),*]
+            }
+
+            fn unique() -> ::std::vec::Vec<::Oxidb::UniqueConstraint> {
+                vec![#(#unique),*]
+            }
+        }
+    })
+}
+
+/// How `#[serde(rename_all = "...")]` on the struct renames its fields.
+#[derive(Clone, Copy)]
+enum RenameRule {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab,
+}
+
+impl RenameRule {
+    /// Renames a field, which is written in snake_case.
+    fn apply(self, field: &str) -> String {
+        let pascal = || {
+            field.split('_').map(|word| {
+                let mut chars = word.chars();
+                chars.next().map(|c| c.to_ascii_uppercase().to_string() + chars.as_str()).unwrap_or_default()
+            }).collect::<String>()
+        };
+        match self {
+            RenameRule::Lower => field.to_string(),
+            RenameRule::Upper | RenameRule::ScreamingSnake => field.to_ascii_uppercase(),
+            RenameRule::Pascal => pascal(),
+            RenameRule::Camel => {
+                let pascal = pascal();
+                let mut chars = pascal.chars();
+                chars.next().map(|c| c.to_ascii_lowercase().to_string() + chars.as_str()).unwrap_or_default()
+            }
+            RenameRule::Kebab => field.replace('_', "-"),
+            RenameRule::ScreamingKebab => field.to_ascii_uppercase().replace('_', "-"),
+        }
+    }
+}
+
+/// The `#[serde(rename_all)]` rule of the struct, if it has one.
+fn rename_rule(input: &DeriveInput) -> syn::Result<Option<RenameRule>> {
+    let mut rule = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("serde")) {
+        let metas = attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)?;
+        for meta in metas.into_iter().filter(|m| m.path().is_ident("rename_all")) {
+            let Meta::NameValue(syn::MetaNameValue { value: syn::Expr::Lit(syn::ExprLit { lit: Lit::Str(s), .. }), .. }) = &meta
+            else {
+                return Err(Error::new_spanned(meta, "OxidbRecord needs one rename_all rule for both directions"));
+            };
+            rule = Some(match s.value().as_str() {
+                "lowercase" | "snake_case" => RenameRule::Lower,
+                "UPPERCASE" => RenameRule::Upper,
+                "PascalCase" => RenameRule::Pascal,
+                "camelCase" => RenameRule::Camel,
+                "SCREAMING_SNAKE_CASE" => RenameRule::ScreamingSnake,
+                "kebab-case" => RenameRule::Kebab,
+                "SCREAMING-KEBAB-CASE" => RenameRule::ScreamingKebab,
+                _ => return Err(Error::new_spanned(s, "unknown rename_all rule")),
+            });
+        }
+    }
+    Ok(rule)
+}
+
+/// The name serde gives the field, and whether serde skips it.
+fn serde_name(field: &syn::Field, rename_all: Option<RenameRule>) -> syn::Result<(String, bool)> {
+    let ident = field.ident.as_ref().expect("named field").to_string();
+    let mut name = match rename_all {
+        Some(rule) => rule.apply(&ident),
+        None => ident,
+    };
+    let mut skipped = false;
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("serde")) {
+        let metas = attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)?;
+        for meta in metas {
+            match meta {
+                Meta::NameValue(nv) if nv.path.is_ident("rename") => {
+                    if let syn::Expr::Lit(syn::ExprLit { lit: Lit::Str(s), .. }) = nv.value {
+                        name = s.value();
+                    }
+                }
+                Meta::Path(path) if path.is_ident("skip") => skipped = true,
+                _ => {}
+            }
+        }
+    }
+    Ok((name, skipped))
+}
+
+/// The column type for a Rust type, and whether the column is nullable.
+fn column_type(ty: &Type) -> (TokenStream2, bool) {
+    let any = quote!(::Oxidb::ColumnType::Any);
+    match ty {
+        Type::Reference(r) => column_type(&r.elem),
+        Type::Paren(p) => column_type(&p.elem),
+        Type::Array(_) | Type::Slice(_) => (quote!(::Oxidb::ColumnType::List), false),
+        Type::Tuple(t) if !t.elems.is_empty() => (quote!(::Oxidb::ColumnType::List), false),
+        Type::Path(p) => {
+            let Some(last) = p.path.segments.last() else { return (any, false) };
+            let ty = match last.ident.to_string().as_str() {
+                "Option" => {
+                    return match &last.arguments {
+                        PathArguments::AngleBracketed(args) => match args.args.first() {
+                            Some(GenericArgument::Type(inner)) => (column_type(inner).0, true),
+                            _ => (any, true),
+                        },
+                        _ => (any, true),
+                    };
+                }
+                "bool" => quote!(::Oxidb::ColumnType::Bool),
+                "i8" | "i16" | "i32" | "i64" | "isize" | "u8" | "u16" | "u32" | "u64" | "usize" => {
+                    quote!(::Oxidb::ColumnType::Int)
+                }
+                "f32" | "f64" => quote!(::Oxidb::ColumnType::Float),
+                "String" | "str" | "char" => quote!(::Oxidb::ColumnType::String),
+                "Vec" | "VecDeque" | "HashSet" | "BTreeSet" => quote!(::Oxidb::ColumnType::List),
+                "HashMap" | "BTreeMap" => quote!(::Oxidb::ColumnType::Map),
+                _ => any,
+            };
+            (ty, false)
+        }
+        _ => (any, false),
+    }
+}
+
+/// Whether the type is plain `u64`.
+fn is_u64(ty: &Type) -> bool {
+    matches!(ty, Type::Path(p) if p.qself.is_none() && p.path.is_ident("u64"))
+}
+
+/// A run of capitals counts as one word, so `HTTPServer` becomes
+/// `http_server`.
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut out = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() {
+            let after_lower = i > 0 && !chars[i - 1].is_uppercase() && chars[i - 1] != '_';
+            let ends_run = i > 0 && chars[i - 1].is_uppercase() && chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if after_lower || ends_run {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
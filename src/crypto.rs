@@ -0,0 +1,79 @@
+use std::fmt;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+const NONCE_LEN: usize = 24;
+
+/// A 256-bit key for encrypting table files and the write-ahead log with
+/// XChaCha20-Poly1305. Where the key comes from (a KMS, a passphrase run
+/// through a KDF, an environment variable) is up to the caller.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    pub fn new(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// Encrypts `plaintext`, returning the random nonce followed by the
+    /// ciphertext and tag. `aad` is authenticated but not stored.
+    fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let cipher = XChaCha20Poly1305::new((&self.0).into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, Payload { msg: plaintext, aad })
+            .expect("encryption only fails for inputs larger than 256 GiB");
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    fn open(&self, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        let (nonce, ciphertext) = sealed.split_at_checked(NONCE_LEN)?;
+        let cipher = XChaCha20Poly1305::new((&self.0).into());
+        cipher.decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad }).ok()
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// Why encrypted data could not be read.
+pub(crate) enum KeyError {
+    /// The data is encrypted but no key was given.
+    Missing,
+    /// None of the keys decrypts it, or it was tampered with.
+    Wrong,
+}
+
+/// The key new data is encrypted with, if any, plus older keys that are
+/// still tried when reading, so data written before a key rotation that was
+/// cut short stays readable.
+#[derive(Clone, Default)]
+pub(crate) struct Keyring {
+    pub(crate) current: Option<EncryptionKey>,
+    pub(crate) old: Vec<EncryptionKey>,
+}
+
+impl Keyring {
+    /// Encrypts with the current key, or returns `None` without one.
+    pub(crate) fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Option<Vec<u8>> {
+        self.current.as_ref().map(|key| key.seal(aad, plaintext))
+    }
+
+    /// Decrypts with whichever key fits. The flag tells whether that was the
+    /// current key; if not, the data should be written again.
+    pub(crate) fn open(&self, aad: &[u8], sealed: &[u8]) -> Result<(Vec<u8>, bool), KeyError> {
+        if self.current.is_none() && self.old.is_empty() {
+            return Err(KeyError::Missing);
+        }
+        self.current.iter()
+            .chain(&self.old)
+            .enumerate()
+            .find_map(|(i, key)| key.open(aad, sealed).map(|plain| (plain, i == 0 && self.current.is_some())))
+            .ok_or(KeyError::Wrong)
+    }
+}
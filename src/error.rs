@@ -0,0 +1,290 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Everything that can go wrong while working with a `MiniDB`.
+///
+/// Errors that originate from a file on disk carry the path of that file, and
+/// errors that concern a single table carry the table name, so a caller can
+/// tell exactly which part of the data directory is at fault.
+#[derive(Debug)]
+pub enum OxidbError {
+    /// An I/O operation on `path` failed.
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A table file exists but does not contain a valid table.
+    CorruptTable {
+        path: PathBuf,
+        table: Option<String>,
+        reason: String,
+    },
+    /// The write-ahead log at `path` holds an unreadable frame at byte
+    /// `offset`; it and everything after it is ignored on replay. After a
+    /// crash in the middle of an append this is expected.
+    CorruptWal {
+        path: PathBuf,
+        offset: u64,
+    },
+    /// A table could not be serialized or deserialized.
+    Serde {
+        path: PathBuf,
+        table: Option<String>,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// The table does not exist.
+    TableNotFound {
+        table: String,
+    },
+    /// A table with this name already exists.
+    TableExists {
+        table: String,
+    },
+    /// The name cannot be used for a table, since it would not map to a file
+    /// of its own in the database directory.
+    InvalidTableName {
+        table: String,
+        reason: &'static str,
+    },
+    /// A record with this id already exists in the table.
+    DuplicateId {
+        table: String,
+        id: u64,
+    },
+    /// The table has no record with this id.
+    RecordNotFound {
+        table: String,
+        id: u64,
+    },
+    /// A record or schema does not satisfy the table's schema.
+    SchemaViolation {
+        table: String,
+        field: String,
+        reason: String,
+    },
+    /// The table already has an index on this field.
+    IndexExists {
+        table: String,
+        field: String,
+    },
+    /// The table has no index on this field.
+    IndexNotFound {
+        table: String,
+        field: String,
+    },
+    /// A write would give two records the same values for a unique constraint.
+    ConstraintViolation {
+        table: String,
+        fields: Vec<String>,
+        conflicting_id: u64,
+    },
+    /// A pagination cursor could not be decoded or belongs to another ordering.
+    InvalidCursor {
+        reason: String,
+    },
+    /// A page of results was asked for with room for no records.
+    InvalidPageSize {
+        size: usize,
+    },
+    /// The record has no field with this name.
+    FieldNotFound {
+        field: String,
+    },
+    /// A field holds a value of another type than the one asked for.
+    TypeMismatch {
+        field: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// The operation is not allowed while a transaction is open: starting
+    /// another one, or saving or loading half-finished changes.
+    TransactionActive,
+    /// `commit` or `rollback` was called without an open transaction.
+    NoTransaction,
+    /// Another `MiniDB` holds the lock on the database directory at `path`.
+    Locked {
+        path: PathBuf,
+    },
+    /// The database was opened read-only.
+    ReadOnly,
+    /// The file at `path` is encrypted but the database was opened without
+    /// a key.
+    MissingKey {
+        path: PathBuf,
+    },
+    /// The file at `path` does not decrypt with the key the database was
+    /// opened with, or it was tampered with.
+    WrongKey {
+        path: PathBuf,
+    },
+    /// A write would take the records held in memory past the limit set
+    /// with [`MiniDB::set_memory_limit`](crate::MiniDB::set_memory_limit).
+    MemoryLimit {
+        limit: usize,
+        needed: usize,
+    },
+    /// A table's schema, indexes or unique constraints differ from what its
+    /// record type declares.
+    SchemaMismatch {
+        table: String,
+        reason: String,
+    },
+    /// A record could not be converted to or from the type of a typed table.
+    Conversion {
+        table: String,
+        id: Option<u64>,
+        reason: String,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, OxidbError>;
+
+/// What [`MiniDB::verify`](crate::MiniDB::verify) found in the database
+/// directory.
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Names of the tables whose file is intact, sorted alphabetically.
+    pub tables: Vec<String>,
+    /// Number of intact entries in the write-ahead log.
+    pub wal_entries: usize,
+    /// Everything that is damaged, each carrying the path of its file.
+    pub problems: Vec<OxidbError>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl OxidbError {
+    pub(crate) fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        OxidbError::Io { path: path.into(), source }
+    }
+
+    /// The path of the file this error concerns, if any.
+    pub fn path(&self) -> Option<&PathBuf> {
+        match self {
+            OxidbError::Io { path, .. }
+            | OxidbError::CorruptTable { path, .. }
+            | OxidbError::CorruptWal { path, .. }
+            | OxidbError::Serde { path, .. }
+            | OxidbError::Locked { path }
+            | OxidbError::MissingKey { path }
+            | OxidbError::WrongKey { path } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// The name of the table this error concerns, if known.
+    pub fn table(&self) -> Option<&str> {
+        match self {
+            OxidbError::CorruptTable { table, .. } | OxidbError::Serde { table, .. } => table.as_deref(),
+            OxidbError::TableNotFound { table }
+            | OxidbError::TableExists { table }
+            | OxidbError::InvalidTableName { table, .. }
+            | OxidbError::DuplicateId { table, .. }
+            | OxidbError::RecordNotFound { table, .. }
+            | OxidbError::SchemaViolation { table, .. }
+            | OxidbError::IndexExists { table, .. }
+            | OxidbError::IndexNotFound { table, .. }
+            | OxidbError::ConstraintViolation { table, .. }
+            | OxidbError::SchemaMismatch { table, .. }
+            | OxidbError::Conversion { table, .. } => Some(table),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for OxidbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OxidbError::Io { path, source } => write!(f, "I/O error on {}: {}", path.display(), source),
+            OxidbError::CorruptTable { path, table: Some(table), reason } => {
+                write!(f, "table '{}' in {} is corrupt: {}", table, path.display(), reason)
+            }
+            OxidbError::CorruptTable { path, table: None, reason } => {
+                write!(f, "table file {} is corrupt: {}", path.display(), reason)
+            }
+            OxidbError::CorruptWal { path, offset } => {
+                write!(f, "write-ahead log {} is unreadable from byte {} on", path.display(), offset)
+            }
+            OxidbError::Serde { path, table: Some(table), source } => {
+                write!(f, "failed to (de)serialize table '{}' ({}): {}", table, path.display(), source)
+            }
+            OxidbError::Serde { path, table: None, source } => {
+                write!(f, "failed to (de)serialize {}: {}", path.display(), source)
+            }
+            OxidbError::TableNotFound { table } => write!(f, "table '{}' does not exist", table),
+            OxidbError::TableExists { table } => write!(f, "table '{}' already exists", table),
+            OxidbError::InvalidTableName { table, reason } => {
+                write!(f, "'{}' is not a valid table name: {}", table, reason)
+            }
+            OxidbError::DuplicateId { table, id } => {
+                write!(f, "a record with id {} already exists in table '{}'", id, table)
+            }
+            OxidbError::RecordNotFound { table, id } => {
+                write!(f, "table '{}' has no record with id {}", table, id)
+            }
+            OxidbError::SchemaViolation { table, field, reason } => {
+                write!(f, "schema violation in table '{}', field '{}': {}", table, field, reason)
+            }
+            OxidbError::IndexExists { table, field } => {
+                write!(f, "table '{}' already has an index on '{}'", table, field)
+            }
+            OxidbError::IndexNotFound { table, field } => {
+                write!(f, "table '{}' has no index on '{}'", table, field)
+            }
+            OxidbError::ConstraintViolation { table, fields, conflicting_id } => write!(
+                f,
+                "unique constraint on ({}) in table '{}' is violated: record {} already has these values",
+                fields.join(", "),
+                table,
+                conflicting_id
+            ),
+            OxidbError::InvalidCursor { reason } => write!(f, "invalid cursor: {}", reason),
+            OxidbError::InvalidPageSize { size } => write!(f, "page size must be at least 1, not {}", size),
+            OxidbError::FieldNotFound { field } => write!(f, "record has no field '{}'", field),
+            OxidbError::TypeMismatch { field, expected, found } => {
+                write!(f, "field '{}' holds a {}, not a {}", field, found, expected)
+            }
+            OxidbError::TransactionActive => write!(f, "a transaction is in progress"),
+            OxidbError::NoTransaction => write!(f, "no transaction is in progress"),
+            OxidbError::Locked { path } => write!(
+                f,
+                "database at {} is already opened by another process or handle; open it read-only to share it",
+                path.display()
+            ),
+            OxidbError::ReadOnly => write!(f, "the database was opened read-only"),
+            OxidbError::MissingKey { path } => {
+                write!(f, "{} is encrypted; open the database with its key", path.display())
+            }
+            OxidbError::WrongKey { path } => {
+                write!(f, "{} cannot be decrypted: wrong key, or the file was tampered with", path.display())
+            }
+            OxidbError::MemoryLimit { limit, needed } => {
+                write!(f, "memory limit of {} bytes reached: {} bytes needed", limit, needed)
+            }
+            OxidbError::SchemaMismatch { table, reason } => {
+                write!(f, "table '{}' does not match its record type: {}", table, reason)
+            }
+            OxidbError::Conversion { table, id: Some(id), reason } => {
+                write!(f, "record {} in table '{}' does not convert: {}", id, table, reason)
+            }
+            OxidbError::Conversion { table, id: None, reason } => {
+                write!(f, "record for table '{}' does not convert: {}", table, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OxidbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OxidbError::Io { source, .. } => Some(source),
+            OxidbError::Serde { source, .. } => Some(&**source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::path::Path;
+use miniz_oxide::inflate::TINFLStatus;
+use serde::{Serialize, Deserialize};
+use serde_json::value::RawValue;
+use crate::checksum::crc32;
+use crate::crypto::{KeyError, Keyring};
+use crate::error::{OxidbError, Result};
+use crate::table::Table;
+
+/// Magic bytes at the start of every binary table file.
+pub(crate) const MAGIC: &[u8; 4] = b"OXDB";
+/// Version of the binary layout written by this build. Files with another
+/// version are refused rather than misread.
+///
+/// The layout is: magic bytes, version byte, flags byte, the CRC-32 of the
+/// payload, payload. A compressed payload starts with its uncompressed size
+/// as a little-endian `u64`.
+pub(crate) const VERSION: u8 = 1;
+/// Magic bytes, version byte, flags byte and checksum.
+const HEADER_LEN: usize = MAGIC.len() + 6;
+
+/// Header flag: the payload is deflate-compressed.
+const FLAG_DEFLATE: u8 = 0x01;
+/// Header flag: the payload is JSON rather than MessagePack.
+const FLAG_JSON: u8 = 0x02;
+/// Header flag: the payload is encrypted (after compression).
+const FLAG_ENCRYPTED: u8 = 0x04;
+const DEFLATE_LEVEL: u8 = 6;
+
+/// A JSON table file: the table with the CRC-32 of its exact bytes as
+/// written. Plain table JSON without the envelope, as written before
+/// checksums existed, is still accepted.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Envelope<'a> {
+    checksum: String,
+    #[serde(borrow)]
+    table: &'a RawValue,
+}
+
+/// How table files are encoded on disk.
+///
+/// Loading recognises both formats, so a database can switch formats at any
+/// time: tables are rewritten in the new format as they are saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// Pretty-printed JSON in `<name>.json`; easy to read and diff.
+    #[default]
+    Json,
+    /// MessagePack behind a small header in `<name>.oxdb`; several times
+    /// smaller and faster to parse.
+    Binary,
+}
+
+impl Format {
+    pub(crate) const ALL: [Format; 2] = [Format::Json, Format::Binary];
+
+    /// The file extension of table files in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Binary => "oxdb",
+        }
+    }
+
+    /// The format of a table file, judged by its extension. Only used to
+    /// find table files; their contents are recognised by themselves.
+    pub(crate) fn of_file(path: &Path) -> Option<Format> {
+        let ext = path.extension()?;
+        Format::ALL.into_iter().find(|f| ext == f.extension())
+    }
+}
+
+/// Compression of table files. Files record their own compression in the
+/// header, so directories with compressed and plain files load fine.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    None,
+    /// DEFLATE; pays off for tables with lots of repeated text.
+    Deflate,
+}
+
+/// Encodes a table file. With a current key in `keys` the payload is
+/// encrypted, after compression, with the header bytes before the checksum
+/// as associated data.
+pub(crate) fn encode_table(
+    format: Format,
+    compression: Compression,
+    keys: &Keyring,
+    path: &Path,
+    table: &Table,
+) -> Result<Vec<u8>> {
+    let serde_error = |e: Box<dyn std::error::Error + Send + Sync>| OxidbError::Serde {
+        path: path.to_path_buf(),
+        table: Some(table.name.clone()),
+        source: e,
+    };
+    let (payload, mut flags) = match (format, compression) {
+        (Format::Json, Compression::None) if keys.current.is_none() => {
+            let json = serde_json::to_vec_pretty(table).map_err(|e| serde_error(e.into()))?;
+            let mut bytes = format!("{{\n\"checksum\": \"{:08x}\",\n\"table\": ", crc32(&json)).into_bytes();
+            bytes.extend_from_slice(&json);
+            bytes.extend_from_slice(b"\n}\n");
+            return Ok(bytes);
+        }
+        // Compressed or encrypted JSON is not readable text anyway, so it
+        // goes behind the binary header like everything else.
+        (Format::Json, _) => (serde_json::to_vec(table).map_err(|e| serde_error(e.into()))?, FLAG_JSON),
+        // Structs are written as maps, not arrays: fields that are skipped
+        // when empty would otherwise shift the ones after them.
+        (Format::Binary, _) => (rmp_serde::to_vec_named(table).map_err(|e| serde_error(e.into()))?, 0),
+    };
+    let payload = match compression {
+        Compression::None => payload,
+        Compression::Deflate => {
+            flags |= FLAG_DEFLATE;
+            let mut compressed = (payload.len() as u64).to_le_bytes().to_vec();
+            compressed.extend_from_slice(&miniz_oxide::deflate::compress_to_vec(&payload, DEFLATE_LEVEL));
+            compressed
+        }
+    };
+    if keys.current.is_some() {
+        flags |= FLAG_ENCRYPTED;
+    }
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&[VERSION, flags]);
+    let payload = keys.seal(&bytes, &payload).unwrap_or(payload);
+    bytes.extend_from_slice(&crc32(&payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+/// Decodes a table file, recognising the binary header by its magic bytes,
+/// and verifies its checksum. Which encoding, compression and encryption the
+/// file uses is read from the file itself. Also returns whether the file is
+/// stale: not encrypted with the current key of `keys`, or not encrypted
+/// while it should be.
+pub(crate) fn decode_table(path: &Path, bytes: &[u8], keys: &Keyring) -> Result<(Table, bool)> {
+    // Until the file parses we only know the table name from the file name.
+    let table = path.file_stem().map(|s| s.to_string_lossy().into_owned());
+    let corrupt = |reason: String| OxidbError::CorruptTable { path: path.to_path_buf(), table: table.clone(), reason };
+    let check = |expected: u32, payload: &[u8]| match crc32(payload) {
+        actual if actual == expected => Ok(()),
+        actual => Err(corrupt(format!("checksum mismatch: expected {:08x}, found {:08x}", expected, actual))),
+    };
+    let parse_json = |json: &[u8]| {
+        serde_json::from_slice(json).map_err(|e| {
+            if e.is_data() {
+                OxidbError::Serde { path: path.to_path_buf(), table: table.clone(), source: e.into() }
+            } else {
+                corrupt(e.to_string())
+            }
+        })
+    };
+
+    let plain_is_stale = keys.current.is_some();
+    let Some(body) = bytes.strip_prefix(MAGIC) else {
+        let table = match serde_json::from_slice::<Envelope>(bytes) {
+            Ok(envelope) => {
+                let expected = u32::from_str_radix(&envelope.checksum, 16)
+                    .map_err(|_| corrupt(format!("invalid checksum '{}'", envelope.checksum)))?;
+                check(expected, envelope.table.get().as_bytes())?;
+                parse_json(envelope.table.get().as_bytes())
+            }
+            Err(_) => parse_json(bytes),
+        };
+        return table.map(|t| (t, plain_is_stale));
+    };
+    let (flags, payload) = match body {
+        [VERSION, flags, a, b, c, d, payload @ ..] => {
+            check(u32::from_le_bytes([*a, *b, *c, *d]), payload)?;
+            (*flags, payload)
+        }
+        [version, ..] if *version != VERSION => {
+            return Err(corrupt(format!(
+                "unsupported binary format version {} (this build reads {})",
+                version, VERSION
+            )));
+        }
+        _ => return Err(corrupt("header is cut short or invalid".to_string())),
+    };
+    if flags & !(FLAG_DEFLATE | FLAG_JSON | FLAG_ENCRYPTED) != 0 {
+        return Err(corrupt(format!("unknown header flags {:#04x}", flags)));
+    }
+    let (decrypted, stale);
+    let payload = if flags & FLAG_ENCRYPTED != 0 {
+        let aad = &bytes[..MAGIC.len() + 2];
+        (decrypted, stale) = keys.open(aad, payload).map_err(|e| match e {
+            KeyError::Missing => OxidbError::MissingKey { path: path.to_path_buf() },
+            KeyError::Wrong => OxidbError::WrongKey { path: path.to_path_buf() },
+        }).map(|(plain, current)| (plain, !current))?;
+        &decrypted[..]
+    } else {
+        stale = plain_is_stale;
+        payload
+    };
+    let inflated;
+    let payload = if flags & FLAG_DEFLATE != 0 {
+        let Some((size, compressed)) = payload.split_first_chunk::<8>() else {
+            return Err(corrupt("compressed payload is cut short".to_string()));
+        };
+        let size = u64::from_le_bytes(*size);
+        let limit = usize::try_from(size).unwrap_or(usize::MAX);
+        inflated = miniz_oxide::inflate::decompress_to_vec_with_limit(compressed, limit).map_err(|e| match e.status {
+            TINFLStatus::HasMoreOutput => corrupt(format!("decompresses to more than {} bytes", limit)),
+            _ => corrupt(format!("cannot decompress: {}", e)),
+        })?;
+        if size != inflated.len() as u64 {
+            return Err(corrupt(format!("decompresses to {} bytes instead of {}", inflated.len(), size)));
+        }
+        &inflated[..]
+    } else {
+        payload
+    };
+    let table = if flags & FLAG_JSON != 0 {
+        parse_json(payload)?
+    } else {
+        rmp_serde::from_slice(payload).map_err(|e| corrupt(e.to_string()))?
+    };
+    Ok((table, stale))
+}
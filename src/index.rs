@@ -0,0 +1,283 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use std::ops::Bound;
+use serde::{Serialize, Deserialize};
+use crate::query::Filter;
+use crate::table::Record;
+use crate::value::Value;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexKind {
+    /// Serves equality and `is_in` lookups.
+    Hash,
+    /// Serves equality, `is_in` and range (`lt`/`le`/`gt`/`ge`) lookups.
+    Ordered,
+}
+
+/// A secondary index on one record field, as stored in the table file. The
+/// index contents are not stored; they are rebuilt when the table is loaded.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct IndexDef {
+    pub field: String,
+    pub kind: IndexKind,
+}
+
+/// A value used as an index key. Keys are equal and ordered according to
+/// [`Value::total_cmp`], so an int and a float with the same numeric value
+/// land on the same key, just as they match the same `eq` filter.
+#[derive(Debug, Clone)]
+pub(crate) struct IndexKey(pub(crate) Value);
+
+impl PartialEq for IndexKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.total_cmp(&other.0) == Ordering::Equal
+    }
+}
+
+impl Eq for IndexKey {}
+
+impl PartialOrd for IndexKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for IndexKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl Hash for IndexKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_value(&self.0, state);
+    }
+}
+
+fn hash_value<H: Hasher>(value: &Value, state: &mut H) {
+    match value {
+        Value::Int(_) | Value::Float(_) => {
+            // Hash every number as a float so 2 and 2.0 collide, with -0.0
+            // and all NaNs folded together to match total_cmp.
+            let f = value.as_f64().unwrap_or_default();
+            let bits = if f == 0.0 { 0 } else if f.is_nan() { f64::NAN.to_bits() } else { f.to_bits() };
+            state.write_u8(2);
+            state.write_u64(bits);
+        }
+        Value::Null => state.write_u8(0),
+        Value::Bool(b) => {
+            state.write_u8(1);
+            b.hash(state);
+        }
+        Value::String(s) => {
+            state.write_u8(3);
+            s.hash(state);
+        }
+        Value::Bytes(b) => {
+            state.write_u8(4);
+            b.hash(state);
+        }
+        Value::Timestamp(t) => {
+            state.write_u8(5);
+            t.hash(state);
+        }
+        Value::List(items) => {
+            state.write_u8(6);
+            state.write_usize(items.len());
+            items.iter().for_each(|v| hash_value(v, state));
+        }
+        Value::Map(map) => {
+            state.write_u8(7);
+            state.write_usize(map.len());
+            for (k, v) in map {
+                k.hash(state);
+                hash_value(v, state);
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Entries {
+    Hash(HashMap<IndexKey, BTreeSet<u64>>),
+    Ordered(BTreeMap<IndexKey, BTreeSet<u64>>),
+}
+
+/// The in-memory contents of one index: field value to record ids. Records
+/// where the field is missing or null are not indexed, since no filter an
+/// index can serve matches them.
+#[derive(Clone)]
+pub(crate) struct Index {
+    pub(crate) def: IndexDef,
+    entries: Entries,
+}
+
+impl Index {
+    pub(crate) fn build<'a>(def: IndexDef, records: impl Iterator<Item = &'a Record>) -> Self {
+        let entries = match def.kind {
+            IndexKind::Hash => Entries::Hash(HashMap::new()),
+            IndexKind::Ordered => Entries::Ordered(BTreeMap::new()),
+        };
+        let mut index = Index { def, entries };
+        records.for_each(|r| index.insert(r));
+        index
+    }
+
+    fn key(&self, record: &Record) -> Option<IndexKey> {
+        record.data.get(&self.def.field).filter(|v| !v.is_null()).cloned().map(IndexKey)
+    }
+
+    pub(crate) fn insert(&mut self, record: &Record) {
+        let Some(key) = self.key(record) else { return };
+        let ids = match &mut self.entries {
+            Entries::Hash(map) => map.entry(key).or_default(),
+            Entries::Ordered(map) => map.entry(key).or_default(),
+        };
+        ids.insert(record.id);
+    }
+
+    pub(crate) fn remove(&mut self, record: &Record) {
+        let Some(key) = self.key(record) else { return };
+        let emptied = match &mut self.entries {
+            Entries::Hash(map) => map.get_mut(&key).map(|ids| ids.remove(&record.id) && ids.is_empty()),
+            Entries::Ordered(map) => map.get_mut(&key).map(|ids| ids.remove(&record.id) && ids.is_empty()),
+        };
+        if emptied == Some(true) {
+            match &mut self.entries {
+                Entries::Hash(map) => map.remove(&key),
+                Entries::Ordered(map) => map.remove(&key),
+            };
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        match &mut self.entries {
+            Entries::Hash(map) => map.clear(),
+            Entries::Ordered(map) => map.clear(),
+        }
+    }
+
+    /// Ids of the records whose field equals `value`.
+    pub(crate) fn lookup(&self, value: &Value) -> BTreeSet<u64> {
+        let key = IndexKey(value.clone());
+        let ids = match &self.entries {
+            Entries::Hash(map) => map.get(&key),
+            Entries::Ordered(map) => map.get(&key),
+        };
+        ids.cloned().unwrap_or_default()
+    }
+
+    /// Ids of the records whose field lies in the range, or `None` for a hash
+    /// index, which cannot answer range questions.
+    fn range(&self, lower: Bound<&Value>, upper: Bound<&Value>) -> Option<BTreeSet<u64>> {
+        let Entries::Ordered(map) = &self.entries else { return None };
+        let wrap = |b: Bound<&Value>| b.map(|v| IndexKey(v.clone()));
+        Some(map.range((wrap(lower), wrap(upper))).flat_map(|(_, ids)| ids.iter().copied()).collect())
+    }
+
+    /// Candidate ids for one filter condition, or `None` if this index cannot
+    /// narrow it down.
+    fn candidates(&self, filter: &Filter) -> Option<BTreeSet<u64>> {
+        match filter {
+            Filter::Eq(f, v) if *f == self.def.field => Some(self.lookup(v)),
+            Filter::In(f, values) if *f == self.def.field => {
+                Some(values.iter().flat_map(|v| self.lookup(v)).collect())
+            }
+            Filter::Lt(f, v) if *f == self.def.field => self.range(Bound::Unbounded, Bound::Excluded(v)),
+            Filter::Le(f, v) if *f == self.def.field => self.range(Bound::Unbounded, Bound::Included(v)),
+            Filter::Gt(f, v) if *f == self.def.field => self.range(Bound::Excluded(v), Bound::Unbounded),
+            Filter::Ge(f, v) if *f == self.def.field => self.range(Bound::Included(v), Bound::Unbounded),
+            _ => None,
+        }
+    }
+}
+
+/// A uniqueness guarantee over one or more fields, as stored in the table
+/// file. Records where any of the fields is missing or null are exempt, as
+/// with SQL unique constraints.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UniqueConstraint {
+    pub fields: Vec<String>,
+}
+
+/// The hash index behind a unique constraint: combined field values to the
+/// id of the one record holding them.
+#[derive(Clone)]
+pub(crate) struct UniqueIndex {
+    pub(crate) def: UniqueConstraint,
+    entries: HashMap<Vec<IndexKey>, u64>,
+}
+
+impl UniqueIndex {
+    /// Builds the index, or returns the ids of the first two records that
+    /// share a key.
+    pub(crate) fn build<'a>(
+        def: UniqueConstraint,
+        records: impl Iterator<Item = &'a Record>,
+    ) -> std::result::Result<Self, (u64, u64)> {
+        let mut index = UniqueIndex { def, entries: HashMap::new() };
+        for record in records {
+            if let Some(existing) = index.conflict(record) {
+                return Err((existing, record.id));
+            }
+            index.insert(record);
+        }
+        Ok(index)
+    }
+
+    fn key(&self, record: &Record) -> Option<Vec<IndexKey>> {
+        self.def.fields.iter()
+            .map(|f| record.data.get(f).filter(|v| !v.is_null()).cloned().map(IndexKey))
+            .collect()
+    }
+
+    /// The id of another record that already holds this record's key.
+    pub(crate) fn conflict(&self, record: &Record) -> Option<u64> {
+        let key = self.key(record)?;
+        self.entries.get(&key).copied().filter(|&id| id != record.id)
+    }
+
+    pub(crate) fn insert(&mut self, record: &Record) {
+        if let Some(key) = self.key(record) {
+            self.entries.insert(key, record.id);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, record: &Record) {
+        if let Some(key) = self.key(record)
+            && self.entries.get(&key) == Some(&record.id)
+        {
+            self.entries.remove(&key);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// Uses the indexes to find a superset of the records matching `filter`.
+/// Only the conditions joined by AND at the top of the filter are looked at;
+/// the first one an index can serve decides the candidates, preferring
+/// equality over ranges. Returns `None` when a full scan is needed.
+pub(crate) fn plan(indexes: &[Index], filter: &Filter) -> Option<BTreeSet<u64>> {
+    let mut conditions = Vec::new();
+    flatten_and(filter, &mut conditions);
+    let exact = |c: &&Filter| matches!(c, Filter::Eq(..) | Filter::In(..));
+    let (equalities, ranges): (Vec<&Filter>, Vec<&Filter>) = conditions.into_iter().partition(exact);
+    equalities.into_iter()
+        .chain(ranges)
+        .find_map(|condition| indexes.iter().find_map(|index| index.candidates(condition)))
+}
+
+fn flatten_and<'f>(filter: &'f Filter, out: &mut Vec<&'f Filter>) {
+    match filter {
+        Filter::And(a, b) => {
+            flatten_and(a, out);
+            flatten_and(b, out);
+        }
+        other => out.push(other),
+    }
+}
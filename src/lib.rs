@@ -1,66 +1,1100 @@
-use std::collections::HashMap;
-use std::fs;
-use std::path::PathBuf;
-use serde::{Serialize, Deserialize};
-
-#[derive(Serialize, Deserialize, Clone)]
-pub struct Record {
-    pub id: u64,
-    pub data: HashMap<String, String>,
-}
+// The package is called `Oxidb`, which rustc flags as a non snake case crate name.
+#![allow(non_snake_case)]
 
-#[derive(Serialize, Deserialize, Default)]
-pub struct Table {
-    pub name: String,
-    pub records: HashMap<u64, Record>,
-}
+// Lets `#[derive(OxidbRecord)]`, which refers to this crate as `::Oxidb`, be
+// used inside the crate as well.
+extern crate self as Oxidb;
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+mod checksum;
+mod crypto;
+mod error;
+mod format;
+mod index;
+mod options;
+mod query;
+mod schema;
+mod shared;
+mod snapshot;
+mod storage;
+mod table;
+mod typed;
+mod value;
+mod wal;
+
+pub use crypto::EncryptionKey;
+pub use error::{OxidbError, Result, VerifyReport};
+pub use format::{Compression, Format};
+pub use index::{IndexDef, IndexKind, UniqueConstraint};
+pub use options::OpenOptions;
+pub use query::{asc, desc, field, not, Cursor, Direction, Field, Filter, Nulls, Page, Query, SortKey};
+pub use schema::{Column, ColumnType, Schema};
+pub use shared::SharedDb;
+pub use snapshot::Snapshot;
+pub use table::{IdStrategy, Record, Table};
+pub use typed::{OxidbRecord, TypedPage, TypedQuery, TypedTable};
+pub use oxidb_derive::OxidbRecord;
+pub use value::Value;
+pub use wal::SyncPolicy;
+
+use crypto::Keyring;
+use wal::{Wal, WalEntry};
 
 pub struct MiniDB {
-    path: PathBuf,
-    tables: HashMap<String, Table>,
+    // The database directory; `None` for a database that lives in memory only.
+    path: Option<PathBuf>,
+    // Shared with snapshots; a table is copied on its first change while a
+    // snapshot still refers to it.
+    tables: HashMap<String, Arc<Table>>,
+    // Tables changed since they were last written to disk.
+    dirty: HashSet<String>,
+    // Tables whose file must be deleted on the next save (dropped or renamed).
+    removed: HashSet<String>,
+    // Set when a table file failed to load. The log may hold changes to that
+    // table that are nowhere else, so checkpoints keep it until a load
+    // succeeds for every table.
+    keep_wal: bool,
+    wal: Wal,
+    tx: Option<Transaction>,
+    format: Format,
+    // Whether `format` was set explicitly. If not, loading takes the format
+    // the table files on disk are in.
+    format_chosen: bool,
+    compression: Compression,
+    keys: Keyring,
+    read_only: bool,
+    // Checkpoint automatically once this long has passed since the last one.
+    auto_save: Option<Duration>,
+    last_save: Instant,
+    // Why the last automatic checkpoint failed, until taken by the caller.
+    auto_save_error: Option<OxidbError>,
+    // Set while a `SharedDb` owns the database. It runs the automatic
+    // checkpoints itself, so that they do not hold the write lock.
+    shared: bool,
+    // Upper bound for `memory_usage`, in bytes.
+    memory_limit: Option<usize>,
+    // The exclusive lock on the directory, released when the database is
+    // dropped. `None` when opened read-only or in memory.
+    lock: Option<File>,
+}
+
+/// Bookkeeping for an open transaction: its changes, which are only logged
+/// on commit, and what the touched tables looked like before, for rollback.
+struct Transaction {
+    log: Vec<WalEntry>,
+    // Every table the transaction touched, as it was before; `None` if it
+    // did not exist yet.
+    undo: HashMap<String, Option<Arc<Table>>>,
+    dirty: HashSet<String>,
+    removed: HashSet<String>,
 }
 
 impl MiniDB {
-    pub fn new(path: &str) -> Self {
-        if let Err(e) = fs::create_dir_all(path) {
-            eprintln!("Failed to create directory: {}", e);
-        }
+    /// Opens the database in directory `path`, creating the directory if
+    /// needed, and takes the lock on it. Fails with [`OxidbError::Locked`]
+    /// while another `MiniDB` has it open for writing, in this process or
+    /// any other.
+    pub fn new(path: &str) -> Result<Self> {
+        fs::create_dir_all(path).map_err(|e| OxidbError::io(path, e))?;
+        let path = PathBuf::from(path);
+        let lock = storage::lock_dir(&path)?;
+        Ok(Self::with_lock(Some(path), Some(lock)))
+    }
+
+    /// Opens an existing database for reading only. It takes no lock, so it
+    /// can be used next to a single writer; [`MiniDB::load`] then sees the
+    /// data as of the writer's last logged change. Every change, and saving,
+    /// fails with [`OxidbError::ReadOnly`].
+    pub fn open_read_only(path: &str) -> Result<Self> {
+        let path = PathBuf::from(path);
+        fs::metadata(&path).map_err(|e| OxidbError::io(&path, e))?;
+        Ok(Self::with_lock(Some(path), None))
+    }
+
+    /// A database that lives in memory only. It has the same API as one on
+    /// disk, but never touches the filesystem: there is no lock and no
+    /// write-ahead log, and saving only marks the tables as clean. Use
+    /// [`MiniDB::persist`] to give it a directory later.
+    pub fn in_memory() -> Self {
+        Self::with_lock(None, None)
+    }
+
+    /// Opens the database in `path` and loads it, failing on the first damaged
+    /// table. Short for `OpenOptions::new().open(path)`; see [`OpenOptions`]
+    /// for the other ways to open a database.
+    pub fn open(path: &str) -> Result<Self> {
+        OpenOptions::new().open(path)
+    }
+
+    /// A database in directory `path` that is read-only if it holds no lock,
+    /// or one in memory only without a path.
+    pub(crate) fn with_lock(path: Option<PathBuf>, lock: Option<File>) -> Self {
         Self {
-            path: PathBuf::from(path),
+            wal: Wal::new(path.as_deref()),
+            read_only: path.is_some() && lock.is_none(),
+            path,
             tables: HashMap::new(),
+            dirty: HashSet::new(),
+            removed: HashSet::new(),
+            keep_wal: false,
+            tx: None,
+            format: Format::default(),
+            format_chosen: false,
+            compression: Compression::default(),
+            keys: Keyring::default(),
+            auto_save: None,
+            last_save: Instant::now(),
+            auto_save_error: None,
+            shared: false,
+            memory_limit: None,
+            lock,
+        }
+    }
+
+    /// Lets go of the directory and its lock but keeps the tables: from now
+    /// on the database lives in memory only.
+    pub(crate) fn into_memory(mut self) -> Self {
+        let mut wal = Wal::new(None);
+        wal.set_keys(self.keys.clone());
+        self.wal = wal;
+        self.path = None;
+        self.lock = None;
+        self.read_only = false;
+        self.dirty.clear();
+        self.removed.clear();
+        self
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn is_in_memory(&self) -> bool {
+        self.path.is_none()
+    }
+
+    /// Moves the database to directory `path`, which must not hold a
+    /// database yet: takes the lock on it, writes every table there and from
+    /// then on logs and saves there like [`MiniDB::new`] would. This is how
+    /// an in-memory database is made durable. A database on disk lets go of
+    /// its old directory without touching it: its table files and write-ahead
+    /// log still hold every change made before the move, so opening it again
+    /// gives the database as it was then.
+    pub fn persist(&mut self, path: &str) -> Result<()> {
+        if self.is_read_only() {
+            return Err(OxidbError::ReadOnly);
+        }
+        if self.tx.is_some() {
+            return Err(OxidbError::TransactionActive);
+        }
+        for name in self.tables.keys() {
+            validate_table_name(name)?;
+        }
+        fs::create_dir_all(path).map_err(|e| OxidbError::io(path, e))?;
+        let dir = PathBuf::from(path);
+        let lock = storage::lock_dir(&dir)?;
+        let entries = fs::read_dir(&dir).map_err(|e| OxidbError::io(&dir, e))?;
+        for entry in entries {
+            let file = entry.map_err(|e| OxidbError::io(&dir, e))?.path();
+            if Format::of_file(&file).is_some() || file.ends_with(wal::WAL_FILE) {
+                let e = std::io::Error::new(std::io::ErrorKind::AlreadyExists, "directory already holds a database");
+                return Err(OxidbError::io(file, e));
+            }
+        }
+        // Nothing changes until every table is written, so a failure leaves
+        // the database where it was.
+        for name in self.tables.keys() {
+            self.write_table_file(&dir, name)?;
+        }
+        let mut wal = Wal::new(Some(&dir));
+        wal.set_keys(self.keys.clone());
+        wal.set_policy(self.wal.policy());
+        self.wal = wal;
+        self.path = Some(dir);
+        self.lock = Some(lock);
+        self.finish_checkpoint()
+    }
+
+    /// A copy of the database as it is now, in memory only, with the same
+    /// settings. Like a [`Snapshot`] it shares tables with this database
+    /// until either side changes them, so copying is cheap, and only holds
+    /// committed changes; unlike a snapshot the copy can be written to.
+    pub fn in_memory_copy(&self) -> MiniDB {
+        let mut copy = MiniDB::in_memory();
+        copy.tables = self.committed_tables();
+        copy.format = self.format;
+        copy.compression = self.compression;
+        copy.keys = self.keys.clone();
+        copy.wal.set_keys(self.keys.clone());
+        copy.memory_limit = self.memory_limit;
+        copy
+    }
+
+    /// Encrypts table files and the write-ahead log with `key`. Call it right
+    /// after opening, before [`MiniDB::load`]: plain table files found by the
+    /// load are then encrypted on the next save, and files encrypted with
+    /// another key are reported as [`OxidbError::WrongKey`].
+    pub fn with_key(mut self, key: EncryptionKey) -> Self {
+        self.keys.current = Some(key);
+        self.wal.set_keys(self.keys.clone());
+        self
+    }
+
+    /// Adds a key that is only used to read files the current key does not
+    /// open, such as those left under the old key when [`MiniDB::rotate_key`]
+    /// was interrupted. Such files are re-encrypted on the next save.
+    pub fn with_old_key(mut self, key: EncryptionKey) -> Self {
+        self.keys.old.push(key);
+        self.wal.set_keys(self.keys.clone());
+        self
+    }
+
+    /// Re-encrypts every table with `key`, or stores them unencrypted with
+    /// `None`, and empties the write-ahead log. Each file is replaced
+    /// atomically; if the process dies halfway, open the database with the
+    /// new key and the old one through [`MiniDB::with_old_key`] and save.
+    pub fn rotate_key(&mut self, key: Option<EncryptionKey>) -> Result<()> {
+        if self.is_read_only() {
+            return Err(OxidbError::ReadOnly);
+        }
+        if self.tx.is_some() {
+            return Err(OxidbError::TransactionActive);
+        }
+        if let Some(old) = self.keys.current.take()
+            && Some(&old) != key.as_ref()
+        {
+            self.keys.old.push(old);
+        }
+        self.keys.current = key;
+        self.wal.set_keys(self.keys.clone());
+        self.dirty.extend(self.tables.keys().cloned());
+        self.checkpoint()
+    }
+
+    /// Sets the format table files are written in. Every table is rewritten in
+    /// the new format on the next save, replacing its old file. Without it, a
+    /// database keeps the format its table files are in when it is loaded.
+    pub fn set_format(&mut self, format: Format) {
+        self.format_chosen = true;
+        if format != self.format {
+            self.format = format;
+            self.dirty.extend(self.tables.keys().cloned());
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Sets the compression for table files of tables without a compression
+    /// of their own. Every such table is rewritten on the next save.
+    pub fn set_compression(&mut self, compression: Compression) {
+        if compression != self.compression {
+            self.compression = compression;
+            let follow = self.tables.values().filter(|t| t.compression.is_none()).map(|t| t.name.clone());
+            self.dirty.extend(follow.collect::<Vec<_>>());
+        }
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Sets the compression for one table's file, or with `None` makes it
+    /// follow the database's. The setting is stored with the table.
+    pub fn set_table_compression(&mut self, table: &str, compression: Option<Compression>) -> Result<()> {
+        if self.lookup(table)?.compression != compression {
+            self.record_change(WalEntry::SetCompression { table: table.to_string(), compression })?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites every table file of the database in `path` in format `to`,
+    /// including changes still in its write-ahead log. This migrates a
+    /// directory between formats without going through the tables by hand.
+    pub fn convert(path: &str, to: Format) -> Result<()> {
+        let mut db = MiniDB::new(path)?;
+        if let Some(problem) = db.load()?.into_iter().next() {
+            return Err(problem);
+        }
+        db.set_format(to);
+        db.dirty.extend(db.tables.keys().cloned());
+        db.checkpoint()
+    }
+
+    /// Sets how often the write-ahead log is flushed to disk.
+    pub fn set_sync_policy(&mut self, policy: SyncPolicy) {
+        self.wal.set_policy(policy);
+    }
+
+    /// Makes the database checkpoint by itself once `interval` has passed
+    /// since the last checkpoint, checked after every change outside a
+    /// transaction and after every commit. A failed automatic checkpoint
+    /// loses nothing, since the change is already in the write-ahead log,
+    /// and does not fail the change: it is retried after the next change, and
+    /// its error is kept for [`MiniDB::take_auto_save_error`].
+    pub fn set_auto_save(&mut self, interval: Option<Duration>) {
+        self.auto_save = interval;
+    }
+
+    /// Why the last automatic checkpoint failed, if it did and no checkpoint
+    /// has succeeded since. Returns it only once.
+    pub fn take_auto_save_error(&mut self) -> Option<OxidbError> {
+        self.auto_save_error.take()
+    }
+
+    /// Limits the approximate size of the records held in memory, in bytes.
+    /// Writes that would go past it fail with [`OxidbError::MemoryLimit`];
+    /// writes that shrink the data are always allowed.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    /// Roughly how many bytes the records of all tables take up in memory.
+    pub fn memory_usage(&self) -> usize {
+        self.tables.values().map(|t| t.data_size).sum()
+    }
+
+    /// Creates an empty table. Fails if a table with this name already exists.
+    pub fn create_table(&mut self, name: &str) -> Result<()> {
+        self.create_table_with_id_strategy(name, IdStrategy::default())
+    }
+
+    /// Creates an empty table that assigns ids for [`MiniDB::insert_new`]
+    /// using `strategy`.
+    pub fn create_table_with_id_strategy(&mut self, name: &str, strategy: IdStrategy) -> Result<()> {
+        validate_table_name(name)?;
+        if self.tables.contains_key(name) {
+            return Err(OxidbError::TableExists { table: name.to_string() });
+        }
+        self.record_change(WalEntry::CreateTable { table: Box::new(Table::new(name, strategy)) })?;
+        Ok(())
+    }
+
+    /// Creates an empty table whose records must satisfy `schema`. The schema
+    /// is stored in the table file alongside the records.
+    pub fn create_table_with_schema(&mut self, name: &str, schema: Schema) -> Result<()> {
+        validate_table_name(name)?;
+        if self.tables.contains_key(name) {
+            return Err(OxidbError::TableExists { table: name.to_string() });
+        }
+        schema.validate(name)?;
+        let mut table = Table::new(name, IdStrategy::default());
+        table.schema = Some(schema);
+        self.record_change(WalEntry::CreateTable { table: Box::new(table) })?;
+        Ok(())
+    }
+
+    /// Creates the table unless it already exists. Returns whether it was created.
+    pub fn create_table_if_not_exists(&mut self, name: &str) -> Result<bool> {
+        if self.tables.contains_key(name) {
+            return Ok(false);
+        }
+        self.create_table(name)?;
+        Ok(true)
+    }
+
+    pub fn table_exists(&self, name: &str) -> bool {
+        self.tables.contains_key(name)
+    }
+
+    /// Names of all tables, sorted alphabetically.
+    pub fn list_tables(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.tables.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Removes the table and all its records. Its file is deleted on the next save.
+    pub fn drop_table(&mut self, name: &str) -> Result<()> {
+        self.lookup(name)?;
+        self.record_change(WalEntry::DropTable { table: name.to_string() })?;
+        Ok(())
+    }
+
+    /// Renames a table. On the next save the table is written under its new
+    /// name and the old file is removed.
+    pub fn rename_table(&mut self, from: &str, to: &str) -> Result<()> {
+        validate_table_name(to)?;
+        if self.tables.contains_key(to) {
+            return Err(OxidbError::TableExists { table: to.to_string() });
+        }
+        self.lookup(from)?;
+        self.record_change(WalEntry::RenameTable { from: from.to_string(), to: to.to_string() })?;
+        Ok(())
+    }
+
+    /// Removes all records from the table but keeps the table itself.
+    pub fn truncate_table(&mut self, name: &str) -> Result<()> {
+        self.lookup(name)?;
+        self.record_change(WalEntry::TruncateTable { table: name.to_string() })?;
+        Ok(())
+    }
+
+    /// Inserts a new record. Fails if the table does not exist or already
+    /// holds a record with the same id; use [`MiniDB::upsert`] to overwrite.
+    pub fn insert(&mut self, table: &str, mut record: Record) -> Result<()> {
+        let t = self.lookup(table)?;
+        if t.records.contains_key(&record.id) {
+            return Err(OxidbError::DuplicateId { table: table.to_string(), id: record.id });
+        }
+        t.conform(&mut record)?;
+        t.check_unique(&record)?;
+        self.record_change(WalEntry::Put { table: table.to_string(), record })?;
+        Ok(())
+    }
+
+    /// Inserts a record with an id picked by the table's [`IdStrategy`] and
+    /// returns that id. Fails with [`OxidbError::DuplicateId`] rather than
+    /// overwrite a record when the sequence has run out at `u64::MAX`.
+    pub fn insert_new(&mut self, table: &str, data: HashMap<String, Value>) -> Result<u64> {
+        let t = self.lookup(table)?;
+        let mut record = Record { id: t.new_record_id(), data };
+        if t.records.contains_key(&record.id) {
+            return Err(OxidbError::DuplicateId { table: table.to_string(), id: record.id });
+        }
+        t.conform(&mut record)?;
+        t.check_unique(&record)?;
+        let id = record.id;
+        self.record_change(WalEntry::Put { table: table.to_string(), record })?;
+        Ok(id)
+    }
+
+    /// Inserts the record, replacing any existing record with the same id.
+    /// Returns the record that was replaced, if there was one.
+    pub fn upsert(&mut self, table: &str, mut record: Record) -> Result<Option<Record>> {
+        let t = self.lookup(table)?;
+        t.conform(&mut record)?;
+        t.check_unique(&record)?;
+        self.record_change(WalEntry::Put { table: table.to_string(), record })
+    }
+
+    pub fn get(&self, table: &str, id: u64) -> Result<Option<&Record>> {
+        Ok(self.lookup(table)?.records.get(&id))
+    }
+
+    /// Creates a secondary index on `field`. Queries filtering on that field
+    /// use it automatically, and it is kept up to date on every write.
+    pub fn create_index(&mut self, table: &str, field: &str, kind: IndexKind) -> Result<()> {
+        let t = self.lookup(table)?;
+        if t.indexes.iter().any(|d| d.field == field) {
+            return Err(OxidbError::IndexExists { table: table.to_string(), field: field.to_string() });
+        }
+        let index = IndexDef { field: field.to_string(), kind };
+        self.record_change(WalEntry::CreateIndex { table: table.to_string(), index })?;
+        Ok(())
+    }
+
+    pub fn drop_index(&mut self, table: &str, field: &str) -> Result<()> {
+        if !self.lookup(table)?.indexes.iter().any(|d| d.field == field) {
+            return Err(OxidbError::IndexNotFound { table: table.to_string(), field: field.to_string() });
+        }
+        self.record_change(WalEntry::DropIndex { table: table.to_string(), field: field.to_string() })?;
+        Ok(())
+    }
+
+    /// The indexes defined on `table`.
+    pub fn indexes(&self, table: &str) -> Result<&[IndexDef]> {
+        Ok(&self.lookup(table)?.indexes)
+    }
+
+    /// Declares that no two records in `table` may share the same values for
+    /// `fields`. Fails if the existing records already do.
+    pub fn add_unique_constraint(&mut self, table: &str, fields: &[&str]) -> Result<()> {
+        let t = self.lookup(table)?;
+        let constraint = UniqueConstraint { fields: fields.iter().map(|f| f.to_string()).collect() };
+        if t.unique.contains(&constraint) {
+            return Ok(());
+        }
+        t.check_new_unique(&constraint)?;
+        self.record_change(WalEntry::AddUnique { table: table.to_string(), constraint })?;
+        Ok(())
+    }
+
+    pub fn drop_unique_constraint(&mut self, table: &str, fields: &[&str]) -> Result<()> {
+        let fields: Vec<String> = fields.iter().map(|f| f.to_string()).collect();
+        if !self.lookup(table)?.unique.iter().any(|c| c.fields == fields) {
+            return Err(OxidbError::IndexNotFound { table: table.to_string(), field: fields.join(", ") });
+        }
+        self.record_change(WalEntry::DropUnique { table: table.to_string(), fields })?;
+        Ok(())
+    }
+
+    /// Starts a query over the records of `table`:
+    /// `db.query("users").filter(field("role").eq("Admin")).records()`.
+    pub fn query(&self, table: &str) -> Query<'_> {
+        Query::new(&self.tables, table)
+    }
+
+    /// A handle on `name` that reads and writes records as `T`:
+    /// `db.table::<User>("users").get(1)`. The records are the same ones the
+    /// untyped methods see.
+    pub fn table<T: OxidbRecord>(&mut self, name: &str) -> TypedTable<'_, T> {
+        TypedTable::new(self, name)
+    }
+
+    /// A handle on the table `T` declares in [`OxidbRecord::TABLE`],
+    /// creating it with the declared id strategy, schema, indexes and unique
+    /// constraints if it does not exist yet. If it does exist, those must
+    /// match what `T` declares, or this fails with
+    /// [`OxidbError::SchemaMismatch`].
+    pub fn open_table<T: OxidbRecord>(&mut self) -> Result<TypedTable<'_, T>> {
+        match self.tables.get(T::TABLE) {
+            Some(table) => typed::check_declared::<T>(table)?,
+            None => {
+                let table = typed::declared_table::<T>()?;
+                self.record_change(WalEntry::CreateTable { table: Box::new(table) })?;
+            }
+        }
+        Ok(TypedTable::new(self, T::TABLE))
+    }
+
+    /// A read-only view of all tables as they are now. Later changes to this
+    /// database do not show up in it. Taking a snapshot is cheap: tables are
+    /// shared until they change, and a table that changes while a snapshot
+    /// holds it is copied once, with the old version freed when the last
+    /// snapshot referring to it is dropped.
+    ///
+    /// Only committed changes are in a snapshot: one taken during a
+    /// transaction shows the tables as they were before it began.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self.committed_tables())
+    }
+
+    /// The tables as they were before the open transaction, if any, began.
+    fn committed_tables(&self) -> HashMap<String, Arc<Table>> {
+        let mut tables = self.tables.clone();
+        if let Some(tx) = &self.tx {
+            for (name, table) in &tx.undo {
+                match table {
+                    Some(table) => tables.insert(name.clone(), table.clone()),
+                    None => tables.remove(name),
+                };
+            }
         }
+        tables
     }
 
-    pub fn create_table(&mut self, name: &str) {
-        self.tables.insert(name.to_string(), Table {
-            name: name.to_string(),
-            records: HashMap::new(),
+    /// Merges `patch` into the data of an existing record. Fields that are not
+    /// in the patch are left untouched.
+    pub fn update(&mut self, table: &str, id: u64, patch: HashMap<String, Value>) -> Result<()> {
+        let t = self.lookup(table)?;
+        let mut record = t.records.get(&id).cloned()
+            .ok_or_else(|| OxidbError::RecordNotFound { table: table.to_string(), id })?;
+        record.data.extend(patch);
+        t.conform(&mut record)?;
+        t.check_unique(&record)?;
+        self.record_change(WalEntry::Put { table: table.to_string(), record })?;
+        Ok(())
+    }
+
+    /// Removes a record and returns it.
+    pub fn delete(&mut self, table: &str, id: u64) -> Result<Record> {
+        if !self.lookup(table)?.records.contains_key(&id) {
+            return Err(OxidbError::RecordNotFound { table: table.to_string(), id });
+        }
+        let removed = self.record_change(WalEntry::Delete { table: table.to_string(), id })?;
+        Ok(removed.expect("record was checked to exist"))
+    }
+
+    /// Same as [`MiniDB::checkpoint`].
+    pub fn save(&mut self) -> Result<()> {
+        self.checkpoint()
+    }
+
+    /// Writes every table changed since it was last written to its
+    /// `<name>.json` or `<name>.oxdb` file, deletes the files of dropped tables and then empties
+    /// the write-ahead log, whose changes are now all in the table files. Each
+    /// file is replaced atomically, so a crash during a checkpoint leaves every
+    /// table file either in its old or its new state, and the log still holds
+    /// whatever did not make it.
+    pub fn checkpoint(&mut self) -> Result<()> {
+        self.write_changes()?;
+        self.finish_checkpoint()
+    }
+
+    /// The part of a checkpoint that only reads the in-memory state: deleting
+    /// the files of dropped tables and writing the dirty tables.
+    pub(crate) fn write_changes(&self) -> Result<()> {
+        if self.is_read_only() {
+            return Err(OxidbError::ReadOnly);
+        }
+        if self.tx.is_some() {
+            return Err(OxidbError::TransactionActive);
+        }
+        let Some(dir) = &self.path else { return Ok(()) };
+        for name in &self.removed {
+            if !self.tables.contains_key(name) {
+                for format in Format::ALL {
+                    storage::remove_durably(&table_file(dir, name, format))?;
+                }
+            }
+        }
+        let mut dirty: Vec<&String> = self.dirty.iter().collect();
+        dirty.sort_unstable();
+        for name in dirty {
+            self.write_table_file(dir, name)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a table in the database's format and removes any copy of it in
+    /// another format, which a format switch leaves behind.
+    fn write_table_file(&self, dir: &Path, name: &str) -> Result<()> {
+        let table = &self.tables[name];
+        let compression = table.compression.unwrap_or(self.compression);
+        storage::write_table(&table_file(dir, name, self.format), self.format, compression, &self.keys, table)?;
+        for format in Format::ALL.into_iter().filter(|&f| f != self.format) {
+            storage::remove_durably(&table_file(dir, name, format))?;
+        }
+        Ok(())
+    }
+
+    /// Marks everything written by [`MiniDB::write_changes`] as clean and
+    /// empties the write-ahead log, unless a table failed to load (see
+    /// [`MiniDB::load`]).
+    pub(crate) fn finish_checkpoint(&mut self) -> Result<()> {
+        self.removed.clear();
+        self.dirty.clear();
+        self.last_save = Instant::now();
+        self.auto_save_error = None;
+        if self.keep_wal {
+            return Ok(());
+        }
+        self.wal.truncate()
+    }
+
+    /// Writes one table to its file, if it changed since it was last written.
+    /// The write-ahead log is left alone, since it may still hold changes to
+    /// other tables; replaying it over the fresh file is harmless.
+    pub fn save_table(&mut self, name: &str) -> Result<()> {
+        if self.is_read_only() {
+            return Err(OxidbError::ReadOnly);
+        }
+        if self.tx.is_some() {
+            return Err(OxidbError::TransactionActive);
+        }
+        self.lookup(name)?;
+        if let Some(dir) = &self.path
+            && self.dirty.contains(name)
+        {
+            self.write_table_file(dir, name)?;
+        }
+        self.dirty.remove(name);
+        Ok(())
+    }
+
+    /// Names of the tables with changes that have not been written to their
+    /// file yet, sorted alphabetically.
+    pub fn dirty_tables(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.dirty.iter().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Reads every table file in the database directory, then replays the
+    /// write-ahead log on top of them to recover changes made after the last
+    /// checkpoint. Temporary files left behind by an interrupted checkpoint
+    /// are deleted; the table file they were meant to replace is still intact.
+    /// A read-only database leaves all files as they are, since the writer
+    /// may still be working on them.
+    ///
+    /// Table files that are damaged or fail their checksum do not stop the
+    /// load: the other tables are loaded and the errors for the damaged ones
+    /// are returned, each carrying the path of the file. The damaged files are
+    /// left alone. The log may hold changes to a damaged table that are not in
+    /// any file, so until a later load succeeds for every table, checkpoints
+    /// write the table files but no longer empty the log: a table restored
+    /// from a backup gets those changes back on the next load.
+    pub fn load(&mut self) -> Result<Vec<OxidbError>> {
+        if self.tx.is_some() {
+            return Err(OxidbError::TransactionActive);
+        }
+        if !self.format_chosen
+            && let Some(format) = self.format_on_disk()?
+        {
+            self.format = format;
+        }
+        if self.is_read_only() {
+            return self.load_beside_writer();
+        }
+        let problems = self.load_tables()?;
+        self.keep_wal = !problems.is_empty();
+        for entry in self.wal.replay()? {
+            self.apply(entry);
+        }
+        Ok(problems)
+    }
+
+    /// Loads while a writer may be checkpointing. The log is read after the
+    /// table files, and the epoch before and after both: if a checkpoint
+    /// emptied the log in between, the tables read may predate the changes it
+    /// held, so the load starts over. Otherwise the log read still holds every
+    /// change since the tables' last checkpoint, and replaying it over table
+    /// files that are partly newer ends in the same state.
+    fn load_beside_writer(&mut self) -> Result<Vec<OxidbError>> {
+        let tables = self.tables.clone();
+        loop {
+            let epoch = self.wal.epoch()?;
+            let problems = self.load_tables()?;
+            let bytes = self.wal.read_bytes()?;
+            if self.wal.epoch()? == epoch {
+                for entry in self.wal.decode(&bytes)?.0 {
+                    self.apply(entry);
+                }
+                return Ok(problems);
+            }
+            self.tables = tables.clone();
+        }
+    }
+
+    /// Reads every table file into memory, returning the errors for the
+    /// damaged ones.
+    fn load_tables(&mut self) -> Result<Vec<OxidbError>> {
+        let mut problems = Vec::new();
+        for path in self.table_files(!self.is_read_only())? {
+            match storage::read_table(&path, &self.keys) {
+                Ok((table, stale)) => {
+                    if stale && !self.is_read_only() {
+                        self.dirty.insert(table.name.clone());
+                    }
+                    self.tables.insert(table.name.clone(), Arc::new(table));
+                }
+                Err(e) => problems.push(e),
+            }
+        }
+        Ok(problems)
+    }
+
+    /// Checks every table file in the directory and the write-ahead log on
+    /// disk, without loading anything: checksums, decoding and unique
+    /// constraints. Works on a read-only database too, so it can check a
+    /// directory another process is writing to.
+    pub fn verify(&self) -> Result<VerifyReport> {
+        let mut report = VerifyReport::default();
+        for path in self.table_files(false)? {
+            match storage::read_table(&path, &self.keys) {
+                Ok((table, _)) => report.tables.push(table.name),
+                Err(e) => report.problems.push(e),
+            }
+        }
+        report.tables.sort_unstable();
+        match self.wal.read() {
+            Ok((entries, torn_at)) => {
+                report.wal_entries = entries.len();
+                if let (Some(offset), Some(path)) = (torn_at, self.wal.path()) {
+                    report.problems.push(OxidbError::CorruptWal { path: path.to_path_buf(), offset });
+                }
+            }
+            Err(e) => report.problems.push(e),
+        }
+        Ok(report)
+    }
+
+    /// The table files in the directory, one per table. With
+    /// `remove_temp_files`, temporary files left behind by an interrupted
+    /// checkpoint are deleted on the way.
+    /// The format most table files in the directory are in, if it has any.
+    fn format_on_disk(&self) -> Result<Option<Format>> {
+        let Some(dir) = &self.path else { return Ok(None) };
+        let (mut json, mut binary) = (0, 0);
+        for entry in fs::read_dir(dir).map_err(|e| OxidbError::io(dir, e))? {
+            let path = entry.map_err(|e| OxidbError::io(dir, e))?.path();
+            match Format::of_file(&path) {
+                Some(Format::Json) => json += 1,
+                Some(Format::Binary) => binary += 1,
+                None => {}
+            }
+        }
+        Ok(match (json, binary) {
+            (0, 0) => None,
+            _ if binary > json => Some(Format::Binary),
+            _ => Some(Format::Json),
+        })
+    }
+
+    fn table_files(&self, remove_temp_files: bool) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let Some(dir) = &self.path else { return Ok(files) };
+        let entries = fs::read_dir(dir).map_err(|e| OxidbError::io(dir, e))?;
+        for entry in entries {
+            let path = entry.map_err(|e| OxidbError::io(dir, e))?.path();
+            if storage::is_temp_file(&path) {
+                if remove_temp_files {
+                    fs::remove_file(&path).map_err(|e| OxidbError::io(&path, e))?;
+                }
+            } else if let Some(format) = Format::of_file(&path) {
+                // A table with files in two formats was caught halfway through
+                // a format switch; the copy in the database's format is current.
+                if format == self.format || !path.with_extension(self.format.extension()).exists() {
+                    files.push(path);
+                }
+            }
+        }
+        files.sort_unstable();
+        Ok(files)
+    }
+
+    /// Runs `f` inside a transaction: its changes are committed together if
+    /// it returns `Ok`, and rolled back if it returns an error or panics:
+    /// `db.transaction(|tx| { tx.insert("orders", order)?; tx.update("stock", id, patch) })`.
+    pub fn transaction<T>(&mut self, f: impl FnOnce(&mut MiniDB) -> Result<T>) -> Result<T> {
+        self.begin()?;
+        match panic::catch_unwind(AssertUnwindSafe(|| f(self))) {
+            Ok(Ok(value)) => {
+                self.commit()?;
+                Ok(value)
+            }
+            Ok(Err(e)) => {
+                self.rollback()?;
+                Err(e)
+            }
+            Err(payload) => {
+                // Nothing of the transaction has been logged yet, so rolling
+                // back leaves the database as it was before `begin`.
+                let _ = self.rollback();
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+
+    /// Starts a transaction. Until [`MiniDB::commit`], changes are visible
+    /// through this database but are not logged, and cannot be saved.
+    pub fn begin(&mut self) -> Result<()> {
+        if self.tx.is_some() {
+            return Err(OxidbError::TransactionActive);
+        }
+        self.tx = Some(Transaction {
+            log: Vec::new(),
+            undo: HashMap::new(),
+            dirty: self.dirty.clone(),
+            removed: self.removed.clone(),
         });
+        Ok(())
     }
 
-    pub fn insert(&mut self, table: &str, record: Record) {
-        if let Some(t) = self.tables.get_mut(table) {
-            t.records.insert(record.id, record);
+    /// Makes the changes of the open transaction durable. They are written to
+    /// the write-ahead log as a single entry, so after a crash either all of
+    /// them are recovered or none are. If that write fails the transaction is
+    /// rolled back.
+    pub fn commit(&mut self) -> Result<()> {
+        let mut tx = self.tx.take().ok_or(OxidbError::NoTransaction)?;
+        if !tx.log.is_empty() {
+            let batch = WalEntry::Batch { entries: std::mem::take(&mut tx.log) };
+            if let Err(e) = self.wal.append(&batch) {
+                self.restore(tx);
+                return Err(e);
+            }
+            self.auto_save();
+        }
+        Ok(())
+    }
+
+    /// Discards the changes of the open transaction, restoring every table it
+    /// touched exactly as it was.
+    pub fn rollback(&mut self) -> Result<()> {
+        let tx = self.tx.take().ok_or(OxidbError::NoTransaction)?;
+        self.restore(tx);
+        Ok(())
+    }
+
+    fn restore(&mut self, tx: Transaction) {
+        for (name, table) in tx.undo {
+            match table {
+                Some(table) => self.tables.insert(name, table),
+                None => self.tables.remove(&name),
+            };
+        }
+        self.dirty = tx.dirty;
+        self.removed = tx.removed;
+    }
+
+    /// Logs a change and then applies it. Inside a transaction the change is
+    /// only logged on commit. Returns the record the change replaced or
+    /// removed, if any.
+    fn record_change(&mut self, entry: WalEntry) -> Result<Option<Record>> {
+        if self.is_read_only() {
+            return Err(OxidbError::ReadOnly);
+        }
+        if let (Some(limit), WalEntry::Put { table, record }) = (self.memory_limit, &entry) {
+            let old = self.tables.get(table).and_then(|t| t.records.get(&record.id)).map_or(0, Record::approx_size);
+            let new = record.approx_size();
+            let needed = self.memory_usage() + new - old;
+            if new > old && needed > limit {
+                return Err(OxidbError::MemoryLimit { limit, needed });
+            }
+        }
+        match &mut self.tx {
+            Some(tx) => {
+                for name in entry.tables() {
+                    if !tx.undo.contains_key(name) {
+                        tx.undo.insert(name.to_string(), self.tables.get(name).cloned());
+                    }
+                }
+                tx.log.push(entry.clone());
+            }
+            None => self.wal.append(&entry)?,
         }
+        let replaced = self.apply(entry);
+        self.auto_save();
+        Ok(replaced)
     }
 
-    pub fn save(&self) {
-        for (name, table) in &self.tables {
-            let file = self.path.join(format!("{}.json", name));
-            fs::write(file, serde_json::to_string_pretty(&table).unwrap()).unwrap();
+    /// Checkpoints if the auto-save interval has passed, unless a `SharedDb`
+    /// takes care of that. An error is kept for the next change to report;
+    /// everything is in the write-ahead log meanwhile.
+    fn auto_save(&mut self) {
+        if !self.shared
+            && self.auto_save_due()
+            && let Err(e) = self.checkpoint()
+        {
+            self.auto_save_error = Some(e);
         }
     }
 
-    pub fn load(&mut self) {
-        for entry in fs::read_dir(&self.path).unwrap() {
-            let path = entry.unwrap().path();
-            if path.extension().unwrap_or_default() == "json" {
-                let data = fs::read_to_string(&path).unwrap();
-                let table: Table = serde_json::from_str(&data).unwrap();
-                self.tables.insert(table.name.clone(), table);
+    /// Whether the auto-save interval has passed with no transaction open.
+    pub(crate) fn auto_save_due(&self) -> bool {
+        self.auto_save.is_some_and(|interval| self.tx.is_none() && self.last_save.elapsed() >= interval)
+    }
+
+    /// Applies a change that has already been validated (or is being replayed
+    /// from the log). Changes to tables that do not exist are ignored, which
+    /// only happens when replaying a log over table files that are newer
+    /// than it.
+    fn apply(&mut self, entry: WalEntry) -> Option<Record> {
+        match &entry {
+            WalEntry::DropTable { table } => {
+                self.dirty.remove(table);
+            }
+            WalEntry::RenameTable { from, to } => {
+                if self.tables.contains_key(from) {
+                    self.dirty.remove(from);
+                    self.dirty.insert(to.clone());
+                }
+            }
+            WalEntry::CreateTable { table } => {
+                self.dirty.insert(table.name.clone());
+            }
+            WalEntry::Batch { .. } => {}
+            WalEntry::TruncateTable { table }
+            | WalEntry::Put { table, .. }
+            | WalEntry::Delete { table, .. }
+            | WalEntry::CreateIndex { table, .. }
+            | WalEntry::DropIndex { table, .. }
+            | WalEntry::AddUnique { table, .. }
+            | WalEntry::DropUnique { table, .. }
+            | WalEntry::SetCompression { table, .. } => {
+                if self.tables.contains_key(table) {
+                    self.dirty.insert(table.clone());
+                }
+            }
+        }
+        match entry {
+            WalEntry::CreateTable { table } => {
+                let mut table = *table;
+                // A freshly created table has no records that could conflict.
+                let _ = table.rebuild_indexes();
+                self.tables.insert(table.name.clone(), Arc::new(table));
+            }
+            WalEntry::DropTable { table } => {
+                if self.tables.remove(&table).is_some() {
+                    self.removed.insert(table);
+                }
+            }
+            WalEntry::RenameTable { from, to } => {
+                if let Some(mut table) = self.tables.remove(&from) {
+                    Arc::make_mut(&mut table).name = to.clone();
+                    self.removed.remove(&to);
+                    self.tables.insert(to, table);
+                    self.removed.insert(from);
+                }
+            }
+            WalEntry::TruncateTable { table } => {
+                if let Some(t) = self.table_mut(&table) {
+                    t.clear();
+                }
+            }
+            WalEntry::Put { table, record } => {
+                let t = self.table_mut(&table)?;
+                t.observe_id(record.id);
+                return t.put(record);
+            }
+            WalEntry::Delete { table, id } => return self.table_mut(&table)?.remove(id),
+            WalEntry::CreateIndex { table, index } => {
+                if let Some(t) = self.table_mut(&table)
+                    && !t.indexes.iter().any(|d| d.field == index.field)
+                {
+                    t.add_index(index);
+                }
+            }
+            WalEntry::DropIndex { table, field } => {
+                if let Some(t) = self.table_mut(&table) {
+                    t.remove_index(&field);
+                }
+            }
+            WalEntry::AddUnique { table, constraint } => {
+                if let Some(t) = self.table_mut(&table)
+                    && !t.unique.contains(&constraint)
+                {
+                    let _ = t.add_unique(constraint);
+                }
+            }
+            WalEntry::DropUnique { table, fields } => {
+                if let Some(t) = self.table_mut(&table) {
+                    t.remove_unique(&fields);
+                }
+            }
+            WalEntry::SetCompression { table, compression } => {
+                if let Some(t) = self.table_mut(&table) {
+                    t.compression = compression;
+                }
+            }
+            WalEntry::Batch { entries } => {
+                for entry in entries {
+                    self.apply(entry);
+                }
             }
         }
+        None
+    }
+
+    fn lookup(&self, name: &str) -> Result<&Table> {
+        query::table(&self.tables, name)
     }
+
+    /// The table to change, copied first if a snapshot still shares it.
+    fn table_mut(&mut self, name: &str) -> Option<&mut Table> {
+        self.tables.get_mut(name).map(Arc::make_mut)
+    }
+}
+
+/// Checks that `name` can be used as a table name. It becomes the stem of the
+/// table's file, so it must be non-empty and hold no path separator, dot or
+/// control character: no escaping the directory, no hidden files and no
+/// clashes with the extensions that tell formats and temporary files apart.
+fn validate_table_name(name: &str) -> Result<()> {
+    let reason = if name.is_empty() {
+        "it is empty"
+    } else if name.contains(['/', '\\']) {
+        "it contains a path separator"
+    } else if name.contains('.') {
+        "it contains a dot"
+    } else if name.chars().any(char::is_control) {
+        "it contains a control character"
+    } else {
+        return Ok(());
+    };
+    Err(OxidbError::InvalidTableName { table: name.to_string(), reason })
+}
+
+fn table_file(dir: &Path, name: &str, format: Format) -> PathBuf {
+    dir.join(format!("{}.{}", name, format.extension()))
 }
 
 // #[cfg(test)]
@@ -100,7 +1134,7 @@ mod tests {
         cleanup_test_dir(test_path);
 
         // Act
-        let _db = MiniDB::new(test_path);
+        let _db = MiniDB::new(test_path).unwrap();
 
         // Assert
         assert!(Path::new(test_path).exists(), "Database directory should be created");
@@ -110,25 +1144,20 @@ mod tests {
 
     #[test]
     fn test_create_table_and_insert_record() {
-        let test_path = "./test_data_2";
-        cleanup_test_dir(test_path);
-
-        let mut db = MiniDB::new(test_path);
-        db.create_table("users");
+        let mut db = MiniDB::in_memory();
+        db.create_table("users").unwrap();
 
         // Maak een record
         let mut record = Record { id: 1, data: HashMap::new() };
         record.data.insert("name".into(), "Stan".into());
         record.data.insert("role".into(), "Admin".into());
 
-        db.insert("users", record.clone());
+        db.insert("users", record.clone()).unwrap();
 
         // Controleer dat de table en record bestaan
-        assert!(db.tables.contains_key("users"));
-        assert_eq!(db.tables["users"].records.len(), 1);
-        assert_eq!(db.tables["users"].records.get(&1).unwrap().data["name"], "Stan");
-
-        cleanup_test_dir(test_path);
+        let stored = db.get("users", 1).unwrap().expect("Record should exist after insert");
+        assert_eq!(stored.data["name"], "Stan");
+        assert!(db.get("users", 2).unwrap().is_none());
     }
 
     #[test]
@@ -137,30 +1166,1723 @@ mod tests {
         cleanup_test_dir(test_path);
 
         // Maak en vul database
-        let mut db = MiniDB::new(test_path);
-        db.create_table("products");
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.create_table("products").unwrap();
 
         let mut rec1 = Record { id: 10, data: HashMap::new() };
         rec1.data.insert("name".into(), "Laptop".into());
         rec1.data.insert("price".into(), "999".into());
 
-        db.insert("products", rec1);
-        db.save();
+        db.insert("products", rec1).unwrap();
+        db.save().unwrap();
 
         // Controleer dat file is aangemaakt
         let file_path = format!("{}/products.json", test_path);
         assert!(Path::new(&file_path).exists(), "Table file should be written to disk");
 
         // Nieuwe DB inladen vanaf disk
-        let mut db2 = MiniDB::new(test_path);
-        db2.load();
+        drop(db);
+        let mut db2 = MiniDB::new(test_path).unwrap();
+        db2.load().unwrap();
 
         // Controleer dat data correct is hersteld
-        let table = db2.tables.get("products").expect("Table 'products' should exist after load");
-        let record = table.records.get(&10).expect("Record should exist after load");
+        let record = db2.get("products", 10)
+            .expect("Table 'products' should exist after load")
+            .expect("Record should exist after load");
         assert_eq!(record.data["name"], "Laptop");
         assert_eq!(record.data["price"], "999");
 
         cleanup_test_dir(test_path);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_insert_into_missing_table_fails() {
+        let mut db = MiniDB::in_memory();
+        let record = Record { id: 1, data: HashMap::new() };
+
+        let err = db.insert("ghosts", record).unwrap_err();
+        assert!(matches!(err, OxidbError::TableNotFound { ref table } if table == "ghosts"));
+    }
+
+    #[test]
+    fn test_load_reports_corrupt_table_file() {
+        let test_path = "./test_data_5";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        let file_path = Path::new(test_path).join("broken.json");
+        fs::write(&file_path, "{ \"name\": \"broken\", \"rec").unwrap();
+
+        // Een kapot bestand mag de load niet laten panicken
+        let problems = db.load().unwrap();
+        assert_eq!(problems.len(), 1);
+        let err = &problems[0];
+        assert!(matches!(err, OxidbError::CorruptTable { .. }));
+        assert_eq!(err.path(), Some(&file_path));
+        assert_eq!(err.table(), Some("broken"));
+        assert!(!db.table_exists("broken"));
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_insert_rejects_duplicate_id_but_upsert_overwrites() {
+        let mut db = MiniDB::in_memory();
+        db.create_table("users").unwrap();
+
+        let mut first = Record { id: 1, data: HashMap::new() };
+        first.data.insert("name".into(), "Stan".into());
+        let mut second = Record { id: 1, data: HashMap::new() };
+        second.data.insert("name".into(), "Eva".into());
+
+        db.insert("users", first).unwrap();
+        let err = db.insert("users", second.clone()).unwrap_err();
+        assert!(matches!(err, OxidbError::DuplicateId { id: 1, .. }));
+        assert_eq!(db.get("users", 1).unwrap().unwrap().data["name"], "Stan");
+
+        let replaced = db.upsert("users", second).unwrap().expect("upsert should return the old record");
+        assert_eq!(replaced.data["name"], "Stan");
+        assert_eq!(db.get("users", 1).unwrap().unwrap().data["name"], "Eva");
+    }
+
+    #[test]
+    fn test_update_merges_and_delete_removes() {
+        let mut db = MiniDB::in_memory();
+        db.create_table("users").unwrap();
+
+        let mut record = Record { id: 1, data: HashMap::new() };
+        record.data.insert("name".into(), "Stan".into());
+        record.data.insert("role".into(), "User".into());
+        db.insert("users", record).unwrap();
+
+        let mut patch = HashMap::new();
+        patch.insert("role".to_string(), "Admin".into());
+        db.update("users", 1, patch).unwrap();
+
+        let stored = db.get("users", 1).unwrap().unwrap();
+        assert_eq!(stored.data["name"], "Stan");
+        assert_eq!(stored.data["role"], "Admin");
+
+        let removed = db.delete("users", 1).unwrap();
+        assert_eq!(removed.id, 1);
+        assert!(db.get("users", 1).unwrap().is_none());
+        assert!(matches!(db.delete("users", 1), Err(OxidbError::RecordNotFound { id: 1, .. })));
+        assert!(matches!(db.update("users", 1, HashMap::new()), Err(OxidbError::RecordNotFound { .. })));
+    }
+
+    #[test]
+    fn test_create_table_twice_fails() {
+        let mut db = MiniDB::in_memory();
+        db.create_table("users").unwrap();
+        db.insert("users", Record { id: 1, data: HashMap::new() }).unwrap();
+
+        assert!(matches!(db.create_table("users"), Err(OxidbError::TableExists { .. })));
+        assert!(!db.create_table_if_not_exists("users").unwrap());
+        // De bestaande records mogen niet gewist zijn
+        assert!(db.get("users", 1).unwrap().is_some());
+
+        assert!(db.create_table_if_not_exists("orders").unwrap());
+        assert_eq!(db.list_tables(), vec!["orders", "users"]);
+    }
+
+    #[test]
+    fn test_drop_rename_and_truncate_tables() {
+        let test_path = "./test_data_9";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.create_table("users").unwrap();
+        db.create_table("logs").unwrap();
+        db.create_table("tmp").unwrap();
+        db.insert("users", Record { id: 1, data: HashMap::new() }).unwrap();
+        db.insert("logs", Record { id: 1, data: HashMap::new() }).unwrap();
+        db.save().unwrap();
+
+        db.drop_table("tmp").unwrap();
+        db.rename_table("users", "members").unwrap();
+        db.truncate_table("logs").unwrap();
+        assert!(!db.table_exists("tmp"));
+        assert!(matches!(db.rename_table("logs", "members"), Err(OxidbError::TableExists { .. })));
+        db.save().unwrap();
+
+        assert!(!Path::new(test_path).join("tmp.json").exists());
+        assert!(!Path::new(test_path).join("users.json").exists());
+        assert!(Path::new(test_path).join("members.json").exists());
+
+        drop(db);
+        let mut db2 = MiniDB::new(test_path).unwrap();
+        db2.load().unwrap();
+        assert_eq!(db2.list_tables(), vec!["logs", "members"]);
+        assert!(db2.get("members", 1).unwrap().is_some());
+        assert!(db2.get("logs", 1).unwrap().is_none());
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_table_names_stay_inside_the_directory() {
+        let mut db = MiniDB::in_memory();
+        for name in ["", "../x", "a/b", "a\\b", "..", ".hidden", "t.json", "tab\tle"] {
+            let err = db.create_table(name).err().unwrap();
+            assert!(matches!(&err, OxidbError::InvalidTableName { table, .. } if table == name), "{}", err);
+        }
+        let schema = Schema::new().column(Column::new("name", ColumnType::String));
+        assert!(matches!(db.create_table_with_schema("../x", schema), Err(OxidbError::InvalidTableName { .. })));
+
+        db.create_table("users_2024").unwrap();
+        let err = db.rename_table("users_2024", "../../etc/foo").err().unwrap();
+        assert!(matches!(err, OxidbError::InvalidTableName { .. }));
+        assert_eq!(err.table(), Some("../../etc/foo"));
+        assert!(db.table_exists("users_2024"));
+
+        // Ook een naam uit een tabelbestand moet geldig zijn en bij het bestand passen
+        let test_path = "./test_data_40";
+        cleanup_test_dir(test_path);
+        fs::create_dir_all(test_path).unwrap();
+        fs::write(Path::new(test_path).join("escape.json"), r#"{"name": "../../escape", "records": {}}"#).unwrap();
+        fs::write(Path::new(test_path).join("other.json"), r#"{"name": "users", "records": {}}"#).unwrap();
+        let mut db = MiniDB::new(test_path).unwrap();
+        let problems = db.load().unwrap();
+        assert_eq!(problems.len(), 2);
+        for problem in &problems {
+            assert!(matches!(problem, OxidbError::CorruptTable { .. }), "{}", problem);
+        }
+        assert!(problems[0].path().unwrap().ends_with("escape.json"));
+        assert!(db.list_tables().is_empty());
+        assert!(!db.verify().unwrap().is_ok());
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_auto_increment_ids_survive_restart() {
+        let test_path = "./test_data_10";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.create_table("users").unwrap();
+        assert_eq!(db.insert_new("users", HashMap::new()).unwrap(), 1);
+        assert_eq!(db.insert_new("users", HashMap::new()).unwrap(), 2);
+        // Een handmatig id schuift de sequence op
+        db.insert("users", Record { id: 10, data: HashMap::new() }).unwrap();
+        assert_eq!(db.insert_new("users", HashMap::new()).unwrap(), 11);
+        db.delete("users", 11).unwrap();
+        db.save().unwrap();
+
+        drop(db);
+        let mut db2 = MiniDB::new(test_path).unwrap();
+        db2.load().unwrap();
+        assert_eq!(db2.insert_new("users", HashMap::new()).unwrap(), 12);
+
+        // Een uitgeputte sequence overschrijft het laatste record niet
+        db2.insert("users", Record::new(u64::MAX).with("name", "Stan")).unwrap();
+        let err = db2.insert_new("users", HashMap::new()).err().unwrap();
+        assert!(matches!(err, OxidbError::DuplicateId { id: u64::MAX, .. }));
+        assert_eq!(db2.get("users", u64::MAX).unwrap().unwrap().get_str("name").unwrap(), "Stan");
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_random_and_time_ordered_ids() {
+        let test_path = "./test_data_11";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.create_table_with_id_strategy("sessions", IdStrategy::Random).unwrap();
+        db.create_table_with_id_strategy("events", IdStrategy::TimeOrdered).unwrap();
+
+        let a = db.insert_new("sessions", HashMap::new()).unwrap();
+        let b = db.insert_new("sessions", HashMap::new()).unwrap();
+        assert_ne!(a, b);
+
+        let ids: Vec<u64> = (0..100).map(|_| db.insert_new("events", HashMap::new()).unwrap()).collect();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]), "time-ordered ids should be increasing");
+        db.save().unwrap();
+
+        drop(db);
+        let mut db2 = MiniDB::new(test_path).unwrap();
+        db2.load().unwrap();
+        let next = db2.insert_new("events", HashMap::new()).unwrap();
+        assert!(next > *ids.last().unwrap());
+
+        // Ook een tijdgeordend id botst nooit met een handmatig ingevoegd id
+        db2.insert("events", Record::new(u64::MAX).with("name", "Stan")).unwrap();
+        let err = db2.insert_new("events", HashMap::new()).err().unwrap();
+        assert!(matches!(err, OxidbError::DuplicateId { id: u64::MAX, .. }));
+        assert_eq!(db2.get("events", u64::MAX).unwrap().unwrap().get_str("name").unwrap(), "Stan");
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_load_table_without_sequence() {
+        let test_path = "./test_data_12";
+        cleanup_test_dir(test_path);
+
+        // Bestand in het oude formaat, zonder id_strategy en next_id
+        fs::create_dir_all(test_path).unwrap();
+        fs::write(
+            Path::new(test_path).join("users.json"),
+            r#"{"name": "users", "records": {"7": {"id": 7, "data": {}}}}"#,
+        ).unwrap();
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.load().unwrap();
+        assert_eq!(db.insert_new("users", HashMap::new()).unwrap(), 8);
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_typed_values_survive_save_and_load() {
+        let test_path = "./test_data_13";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.create_table("products").unwrap();
+        let record = Record::new(1)
+            .with("name", "Laptop")
+            .with("price", 999)
+            .with("weight", 1.5)
+            .with("in_stock", true)
+            .with("thumbnail", vec![0x89u8, b'P', b'N', b'G'])
+            .with("added", Value::Timestamp(1_700_000_000_000))
+            .with("tags", vec![Value::from("sale"), Value::Null]);
+        db.insert("products", record.clone()).unwrap();
+        db.save().unwrap();
+
+        drop(db);
+        let mut db2 = MiniDB::new(test_path).unwrap();
+        db2.load().unwrap();
+        let loaded = db2.get("products", 1).unwrap().unwrap();
+        assert_eq!(loaded, &record);
+        assert_eq!(loaded.get_i64("price").unwrap(), 999);
+        assert_eq!(loaded.get_f64("weight").unwrap(), 1.5);
+        assert_eq!(loaded.get_str("name").unwrap(), "Laptop");
+        assert_eq!(loaded.get_timestamp("added").unwrap(), 1_700_000_000_000);
+
+        assert!(matches!(
+            loaded.get_i64("name"),
+            Err(OxidbError::TypeMismatch { expected: "int", found: "string", .. })
+        ));
+        assert!(matches!(loaded.get_str("missing"), Err(OxidbError::FieldNotFound { .. })));
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_schema_enforces_types_defaults_and_not_null() {
+        let test_path = "./test_data_14";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        let schema = Schema::new()
+            .column(Column::new("name", ColumnType::String))
+            .column(Column::new("age", ColumnType::Int).nullable())
+            .column(Column::new("role", ColumnType::String).default("User"));
+        db.create_table_with_schema("users", schema.clone()).unwrap();
+
+        db.insert("users", Record::new(1).with("name", "Stan")).unwrap();
+        assert_eq!(db.get("users", 1).unwrap().unwrap().data["role"], "User");
+
+        let missing_name = db.insert("users", Record::new(2).with("age", 30));
+        assert!(matches!(missing_name, Err(OxidbError::SchemaViolation { ref field, .. }) if field == "name"));
+
+        let wrong_type = db.insert("users", Record::new(2).with("name", "Eva").with("age", "dertig"));
+        assert!(matches!(wrong_type, Err(OxidbError::SchemaViolation { ref field, .. }) if field == "age"));
+
+        // Typfout in een veldnaam
+        let typo = db.insert("users", Record::new(2).with("name", "Eva").with("rol", "Admin"));
+        assert!(matches!(typo, Err(OxidbError::SchemaViolation { ref field, .. }) if field == "rol"));
+
+        let mut patch = HashMap::new();
+        patch.insert("name".to_string(), Value::Null);
+        assert!(matches!(db.update("users", 1, patch), Err(OxidbError::SchemaViolation { .. })));
+        assert_eq!(db.get("users", 1).unwrap().unwrap().data["name"], "Stan");
+
+        db.save().unwrap();
+        drop(db);
+        let mut db2 = MiniDB::new(test_path).unwrap();
+        db2.load().unwrap();
+        assert_eq!(db2.tables["users"].schema.as_ref(), Some(&schema));
+        assert!(db2.insert("users", Record::new(3)).is_err());
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_schema_with_extra_fields_allowed() {
+        let mut db = MiniDB::in_memory();
+        let schema = Schema::new()
+            .column(Column::new("price", ColumnType::Float))
+            .column(Column::new("discount", ColumnType::Float).default(0))
+            .allow_extra_fields(true);
+        db.create_table_with_schema("products", schema).unwrap();
+
+        db.insert("products", Record::new(1).with("price", 999).with("name", "Laptop")).unwrap();
+        let stored = db.get("products", 1).unwrap().unwrap();
+        assert_eq!(stored.data["price"], Value::Float(999.0));
+        // Een int als standaardwaarde wordt net zo omgezet als een opgegeven waarde
+        assert_eq!(stored.data["discount"], Value::Float(0.0));
+
+        let bad_default = Schema::new().column(Column::new("n", ColumnType::Int).default("nul"));
+        assert!(db.create_table_with_schema("broken", bad_default).is_err());
+        assert!(!db.table_exists("broken"));
+    }
+
+    fn seed_users(db: &mut MiniDB) {
+        db.create_table("users").unwrap();
+        let users = [
+            ("Stan", "Admin", Some(35)),
+            ("Eva", "User", Some(28)),
+            ("Piet", "Admin", Some(52)),
+            ("Anna", "User", None),
+        ];
+        for (name, role, age) in users {
+            let mut data = HashMap::new();
+            data.insert("name".to_string(), Value::from(name));
+            data.insert("role".to_string(), Value::from(role));
+            data.insert("age".to_string(), Value::from(age));
+            db.insert_new("users", data).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_query_with_filters() {
+        let mut db = MiniDB::in_memory();
+        seed_users(&mut db);
+
+        let mut ids = db.query("users")
+            .filter(field("role").eq("Admin"))
+            .and(field("age").gt(30))
+            .ids()
+            .unwrap();
+        ids.sort();
+        assert_eq!(ids, vec![1, 3]);
+
+        assert_eq!(db.query("users").filter(field("age").is_null()).count().unwrap(), 1);
+        assert_eq!(db.query("users").filter(field("age").le(35.0)).count().unwrap(), 2);
+        assert_eq!(db.query("users").filter(field("name").is_in(["Eva", "Anna"])).count().unwrap(), 2);
+        assert_eq!(db.query("users").filter(field("name").starts_with("P")).count().unwrap(), 1);
+        assert_eq!(db.query("users").filter(field("name").contains("nn")).count().unwrap(), 1);
+        // Null doet niet mee bij ne
+        assert_eq!(db.query("users").filter(field("age").ne(28)).count().unwrap(), 2);
+        assert_eq!(db.query("users").filter(not(field("role").eq("Admin"))).count().unwrap(), 2);
+
+        let records = db.query("users")
+            .filter(field("name").eq("Anna"))
+            .or(field("age").ge(50))
+            .records()
+            .unwrap();
+        let mut names: Vec<&str> = records.iter().map(|r| r.get_str("name").unwrap()).collect();
+        names.sort();
+        assert_eq!(names, vec!["Anna", "Piet"]);
+
+        assert!(matches!(db.query("ghosts").count(), Err(OxidbError::TableNotFound { .. })));
+    }
+
+    #[test]
+    fn test_query_ordering_limit_and_offset() {
+        let mut db = MiniDB::in_memory();
+        seed_users(&mut db);
+
+        let names = |records: Vec<&Record>| -> Vec<String> {
+            records.iter().map(|r| r.get_str("name").unwrap().to_string()).collect()
+        };
+
+        let by_age = db.query("users").order_by(asc("age")).records().unwrap();
+        assert_eq!(names(by_age), vec!["Eva", "Stan", "Piet", "Anna"]);
+
+        let by_age_desc = db.query("users").order_by(desc("age").nulls_last()).records().unwrap();
+        assert_eq!(names(by_age_desc), vec!["Piet", "Stan", "Eva", "Anna"]);
+
+        let by_role_then_name = db.query("users")
+            .order_by(asc("role"))
+            .order_by(desc("name"))
+            .offset(1)
+            .limit(2)
+            .records()
+            .unwrap();
+        assert_eq!(names(by_role_then_name), vec!["Piet", "Eva"]);
+
+        // Zonder order_by: altijd op id
+        assert_eq!(db.query("users").ids().unwrap(), vec![1, 2, 3, 4]);
+        assert_eq!(db.query("users").limit(3).count().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_cursor_pagination_is_stable_under_inserts() {
+        let mut db = MiniDB::in_memory();
+        db.create_table("items").unwrap();
+        for rank in [5, 3, 9, 1, 7] {
+            db.insert_new("items", HashMap::from([("rank".to_string(), Value::from(rank))])).unwrap();
+        }
+
+        let first = db.query("items").order_by(asc("rank")).page(2).unwrap();
+        let ranks = |page: &Page| -> Vec<i64> { page.records.iter().map(|r| r.get_i64("rank").unwrap()).collect() };
+        assert_eq!(ranks(&first), vec![1, 3]);
+        let cursor = first.next.clone().expect("there should be a second page");
+
+        // Een insert vóór de cursor verschuift de volgende pagina niet
+        db.insert_new("items", HashMap::from([("rank".to_string(), Value::from(2))])).unwrap();
+
+        let second = db.query("items").order_by(asc("rank")).after(cursor.clone()).page(2).unwrap();
+        assert_eq!(ranks(&second), vec![5, 7]);
+        let third = db.query("items").order_by(asc("rank")).after(second.next.unwrap()).page(2).unwrap();
+        assert_eq!(ranks(&third), vec![9]);
+        assert!(third.next.is_none());
+
+        let wrong_order = db.query("items").order_by(desc("name")).after(cursor).records();
+        assert!(matches!(wrong_order, Err(OxidbError::InvalidCursor { .. })));
+        let garbage = db.query("items").order_by(asc("rank")).after(Cursor::from("zz".to_string())).records();
+        assert!(matches!(garbage, Err(OxidbError::InvalidCursor { .. })));
+
+        // Een lege pagina heeft geen positie om verder te gaan
+        let empty = db.query("items").order_by(asc("rank")).page(0);
+        assert!(matches!(empty, Err(OxidbError::InvalidPageSize { size: 0 })));
+    }
+
+    #[test]
+    fn test_indexes_stay_in_sync_and_are_rebuilt_on_load() {
+        let test_path = "./test_data_19";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        seed_users(&mut db);
+        db.create_index("users", "role", IndexKind::Hash).unwrap();
+        db.create_index("users", "age", IndexKind::Ordered).unwrap();
+        assert!(matches!(
+            db.create_index("users", "age", IndexKind::Hash),
+            Err(OxidbError::IndexExists { .. })
+        ));
+
+        let admins = field("role").eq("Admin");
+        assert!(index::plan(&db.tables["users"].index_data, &admins).is_some());
+        assert_eq!(db.query("users").filter(admins.clone()).ids().unwrap(), vec![1, 3]);
+        assert_eq!(db.query("users").filter(field("age").ge(35)).ids().unwrap(), vec![1, 3]);
+        assert_eq!(db.query("users").filter(field("age").lt(30.5)).ids().unwrap(), vec![2]);
+
+        // Index bijwerken bij update en delete
+        db.update("users", 2, HashMap::from([("role".to_string(), Value::from("Admin"))])).unwrap();
+        db.delete("users", 1).unwrap();
+        db.upsert("users", Record::new(5).with("role", "Admin").with("age", 41)).unwrap();
+        assert_eq!(db.query("users").filter(admins.clone()).ids().unwrap(), vec![2, 3, 5]);
+        assert_eq!(db.query("users").filter(field("age").gt(40)).ids().unwrap(), vec![3, 5]);
+        db.save().unwrap();
+
+        drop(db);
+        let mut db2 = MiniDB::new(test_path).unwrap();
+        db2.load().unwrap();
+        assert_eq!(db2.indexes("users").unwrap().len(), 2);
+        assert!(index::plan(&db2.tables["users"].index_data, &admins).is_some());
+        assert_eq!(db2.query("users").filter(admins.clone()).ids().unwrap(), vec![2, 3, 5]);
+
+        db2.drop_index("users", "role").unwrap();
+        assert!(index::plan(&db2.tables["users"].index_data, &admins).is_none());
+        assert_eq!(db2.query("users").filter(admins).ids().unwrap(), vec![2, 3, 5]);
+
+        db2.truncate_table("users").unwrap();
+        assert_eq!(db2.query("users").filter(field("age").gt(0)).count().unwrap(), 0);
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_unique_constraints() {
+        let test_path = "./test_data_20";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.create_table("users").unwrap();
+        db.add_unique_constraint("users", &["email"]).unwrap();
+        db.add_unique_constraint("users", &["first", "last"]).unwrap();
+
+        db.insert("users", Record::new(1).with("email", "stan@example.com").with("first", "Stan").with("last", "S")).unwrap();
+        let err = db.insert("users", Record::new(2).with("email", "stan@example.com")).unwrap_err();
+        assert!(matches!(err, OxidbError::ConstraintViolation { conflicting_id: 1, .. }));
+
+        let err = db.insert("users", Record::new(2).with("first", "Stan").with("last", "S")).unwrap_err();
+        assert!(matches!(err, OxidbError::ConstraintViolation { ref fields, .. } if fields.len() == 2));
+
+        // Null telt niet mee, net als in SQL
+        db.insert("users", Record::new(2).with("first", "Stan")).unwrap();
+        db.insert("users", Record::new(3).with("first", "Stan")).unwrap();
+
+        let change_email = HashMap::from([("email".to_string(), Value::from("stan@example.com"))]);
+        assert!(db.update("users", 3, change_email.clone()).is_err());
+        // Een record mag zijn eigen waarde houden
+        db.update("users", 1, change_email).unwrap();
+        db.delete("users", 1).unwrap();
+        db.insert("users", Record::new(4).with("email", "stan@example.com")).unwrap();
+        db.save().unwrap();
+
+        drop(db);
+        let mut db2 = MiniDB::new(test_path).unwrap();
+        db2.load().unwrap();
+        assert_eq!(db2.tables["users"].unique.len(), 2);
+        let err = db2.insert("users", Record::new(5).with("email", "stan@example.com")).unwrap_err();
+        assert!(matches!(err, OxidbError::ConstraintViolation { conflicting_id: 4, .. }));
+
+        // Bestaande dubbele waarden blokkeren een nieuwe constraint
+        assert!(db2.add_unique_constraint("users", &["first"]).is_err());
+        db2.drop_unique_constraint("users", &["email"]).unwrap();
+        db2.insert("users", Record::new(5).with("email", "stan@example.com")).unwrap();
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_save_leaves_no_temp_files_and_load_cleans_them_up() {
+        let test_path = "./test_data_21";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.create_table("users").unwrap();
+        db.insert("users", Record::new(1).with("name", "Stan")).unwrap();
+        db.save().unwrap();
+        db.insert("users", Record::new(2).with("name", "Eva")).unwrap();
+        db.save().unwrap();
+
+        let mut names: Vec<String> = fs::read_dir(test_path).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["oxidb.epoch", "oxidb.lock", "users.json"]);
+
+        // Een save die halverwege stopte laat een half bestand achter
+        let stray = Path::new(test_path).join("users.json.tmp");
+        fs::write(&stray, "{ \"name\": \"users\", \"reco").unwrap();
+
+        drop(db);
+        let mut db2 = MiniDB::new(test_path).unwrap();
+        db2.load().unwrap();
+        assert!(!stray.exists(), "stray temp file should be removed on load");
+        assert_eq!(db2.query("users").count().unwrap(), 2);
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_wal_recovers_changes_made_after_the_last_save() {
+        let test_path = "./test_data_22";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        seed_users(&mut db);
+        db.save().unwrap();
+        db.update("users", 1, HashMap::from([("age".to_string(), Value::from(36))])).unwrap();
+        db.delete("users", 2).unwrap();
+        db.create_index("users", "role", IndexKind::Hash).unwrap();
+        db.create_table("orders").unwrap();
+        db.insert("orders", Record::new(1).with("item", "boek")).unwrap();
+        // Geen save: het proces "crasht" hier
+        drop(db);
+
+        let mut db2 = MiniDB::new(test_path).unwrap();
+        db2.load().unwrap();
+        assert_eq!(db2.get("users", 1).unwrap().unwrap().get_i64("age").unwrap(), 36);
+        assert!(db2.get("users", 2).unwrap().is_none());
+        assert_eq!(db2.indexes("users").unwrap().len(), 1);
+        assert_eq!(db2.get("orders", 1).unwrap().unwrap().get_str("item").unwrap(), "boek");
+        // De sequence loopt ook na replay gewoon door
+        assert_eq!(db2.insert_new("users", HashMap::new()).unwrap(), 5);
+
+        // Een checkpoint schrijft alles weg en leegt de log
+        db2.checkpoint().unwrap();
+        assert!(!Path::new(test_path).join(wal::WAL_FILE).exists());
+        drop(db2);
+        let mut db3 = MiniDB::new(test_path).unwrap();
+        db3.load().unwrap();
+        assert_eq!(db3.query("users").count().unwrap(), 4);
+        assert!(db3.table_exists("orders"));
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_wal_ignores_torn_tail() {
+        let test_path = "./test_data_23";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.set_sync_policy(SyncPolicy::EveryMillis(50));
+        db.create_table("users").unwrap();
+        db.insert("users", Record::new(1).with("name", "Stan")).unwrap();
+        drop(db);
+
+        // Een append die halverwege stopte
+        let wal_path = Path::new(test_path).join(wal::WAL_FILE);
+        let mut bytes = fs::read(&wal_path).unwrap();
+        let intact = bytes.len();
+        bytes.extend_from_slice(&[200, 0, 0, 0, 1, 2, 3, 4, b'{']);
+        fs::write(&wal_path, &bytes).unwrap();
+
+        let mut db2 = MiniDB::new(test_path).unwrap();
+        db2.load().unwrap();
+        assert_eq!(db2.query("users").count().unwrap(), 1);
+        assert_eq!(fs::metadata(&wal_path).unwrap().len(), intact as u64);
+
+        // Nieuwe writes komen achter het laatste intacte frame
+        db2.insert("users", Record::new(2).with("name", "Eva")).unwrap();
+        drop(db2);
+        let mut db3 = MiniDB::new(test_path).unwrap();
+        db3.load().unwrap();
+        assert_eq!(db3.query("users").count().unwrap(), 2);
+        drop(db3);
+
+        // Een intact frame dat niet te decoderen is, is geen afgebroken staart:
+        // laden faalt en de entries erachter blijven staan
+        let good = fs::read(&wal_path).unwrap();
+        let payload = br#"{"op":"unknown_op"}"#;
+        let mut bytes = good.clone();
+        let at = bytes.len();
+        bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&checksum::crc32(payload).to_le_bytes());
+        bytes.extend_from_slice(payload);
+        bytes.extend_from_slice(&good);
+        fs::write(&wal_path, &bytes).unwrap();
+        let mut db4 = MiniDB::new(test_path).unwrap();
+        let err = db4.load().err().unwrap();
+        assert!(matches!(&err, OxidbError::Serde { path, .. } if path == &wal_path), "{}", err);
+        assert!(err.to_string().contains(&format!("byte {}", at)), "{}", err);
+        assert_eq!(fs::metadata(&wal_path).unwrap().len(), bytes.len() as u64);
+        assert!(!db4.verify().unwrap().is_ok());
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_failed_append_does_not_hide_later_writes() {
+        let test_path = "./test_data_39";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.create_table("users").unwrap();
+        db.insert("users", Record::new(1)).unwrap();
+
+        // Een append die halverwege het frame faalt laat geen rommel achter
+        let wal_path = Path::new(test_path).join(wal::WAL_FILE);
+        let len = fs::metadata(&wal_path).unwrap().len();
+        db.wal.fail_next_append_after(5);
+        assert!(matches!(db.insert("users", Record::new(2)), Err(OxidbError::Io { .. })));
+        assert_eq!(fs::metadata(&wal_path).unwrap().len(), len);
+        assert!(db.get("users", 2).unwrap().is_none());
+        db.insert("users", Record::new(3)).unwrap();
+        db.insert("users", Record::new(4)).unwrap();
+        drop(db);
+
+        let db = MiniDB::open(test_path).unwrap();
+        assert_eq!(db.query("users").ids().unwrap(), [1, 3, 4]);
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_damaged_table_keeps_its_logged_changes() {
+        let test_path = "./test_data_41";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.create_table("orders").unwrap();
+        db.create_table("users").unwrap();
+        db.insert("orders", Record::new(1)).unwrap();
+        db.save().unwrap();
+        // Deze staat alleen in de WAL
+        db.insert("orders", Record::new(2)).unwrap();
+        drop(db);
+
+        let orders_path = Path::new(test_path).join("orders.json");
+        let backup = fs::read(&orders_path).unwrap();
+        fs::write(&orders_path, "{ kapot").unwrap();
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        assert_eq!(db.load().unwrap().len(), 1);
+        db.insert("users", Record::new(7)).unwrap();
+        db.save().unwrap();
+
+        // Zolang orders niet geladen is blijft de WAL staan
+        assert!(Path::new(test_path).join(wal::WAL_FILE).exists());
+        drop(db);
+
+        // Na het terugzetten van de backup komt record 2 weer terug
+        fs::write(&orders_path, backup).unwrap();
+        let mut db = MiniDB::new(test_path).unwrap();
+        assert!(db.load().unwrap().is_empty());
+        assert_eq!(db.query("orders").ids().unwrap(), [1, 2]);
+        assert_eq!(db.query("users").ids().unwrap(), [7]);
+
+        // Nu alles geladen is mag een checkpoint de WAL weer legen
+        db.save().unwrap();
+        assert!(!Path::new(test_path).join(wal::WAL_FILE).exists());
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_sync_every_millis_syncs_on_next_append() {
+        let test_path = "./test_data_37";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.set_sync_policy(SyncPolicy::EveryMillis(50));
+        db.create_table("users").unwrap();
+        db.insert("users", Record::new(1).with("name", "Stan")).unwrap();
+        assert!(db.wal.has_unsynced());
+
+        // Er loopt geen timer: na het interval is er nog steeds niets gesynct
+        std::thread::sleep(Duration::from_millis(80));
+        assert!(db.wal.has_unsynced());
+
+        // De eerste append na het interval synct alles
+        db.insert("users", Record::new(2).with("name", "Eva")).unwrap();
+        assert!(!db.wal.has_unsynced());
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_save_only_writes_dirty_tables() {
+        let test_path = "./test_data_24";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        seed_users(&mut db);
+        db.create_table("orders").unwrap();
+        assert_eq!(db.dirty_tables(), vec!["orders", "users"]);
+        db.save().unwrap();
+        assert!(db.dirty_tables().is_empty());
+
+        // Een ongewijzigde tabel wordt niet opnieuw geschreven
+        let orders_file = Path::new(test_path).join("orders.json");
+        fs::remove_file(&orders_file).unwrap();
+        db.delete("users", 4).unwrap();
+        assert_eq!(db.dirty_tables(), vec!["users"]);
+        db.save().unwrap();
+        assert!(!orders_file.exists());
+
+        // save_table schrijft alleen de gevraagde tabel
+        db.insert("orders", Record::new(1).with("item", "boek")).unwrap();
+        db.delete("users", 3).unwrap();
+        db.save_table("orders").unwrap();
+        assert!(orders_file.exists());
+        assert_eq!(db.dirty_tables(), vec!["users"]);
+        assert!(matches!(db.save_table("missing"), Err(OxidbError::TableNotFound { .. })));
+
+        // Een gedropte tabel is niet meer dirty, maar zijn bestand verdwijnt wel
+        db.drop_table("users").unwrap();
+        assert!(db.dirty_tables().is_empty());
+        db.save().unwrap();
+        assert!(!Path::new(test_path).join("users.json").exists());
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_transaction_commits_or_rolls_back_across_tables() {
+        let test_path = "./test_data_25";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.create_table("orders").unwrap();
+        db.create_table("stock").unwrap();
+        db.insert("stock", Record::new(1).with("item", "boek").with("count", 1)).unwrap();
+        db.save().unwrap();
+
+        let take_one = |tx: &mut MiniDB, order: u64| -> Result<()> {
+            tx.insert("orders", Record::new(order).with("item", 1))?;
+            let count = tx.get("stock", 1)?.unwrap().get_i64("count")?;
+            if count == 0 {
+                return Err(OxidbError::SchemaViolation {
+                    table: "stock".to_string(),
+                    field: "count".to_string(),
+                    reason: "out of stock".to_string(),
+                });
+            }
+            tx.update("stock", 1, HashMap::from([("count".to_string(), Value::from(count - 1))]))
+        };
+
+        db.transaction(|tx| take_one(tx, 1)).unwrap();
+        assert!(db.transaction(|tx| take_one(tx, 2)).is_err());
+        // De mislukte order is in beide tabellen teruggedraaid
+        assert!(db.get("orders", 2).unwrap().is_none());
+        assert_eq!(db.get("stock", 1).unwrap().unwrap().get_i64("count").unwrap(), 0);
+
+        db.begin().unwrap();
+        db.create_table("audit").unwrap();
+        db.drop_table("orders").unwrap();
+        assert!(matches!(db.begin(), Err(OxidbError::TransactionActive)));
+        assert!(matches!(db.save(), Err(OxidbError::TransactionActive)));
+        db.rollback().unwrap();
+        assert!(!db.table_exists("audit"));
+        assert_eq!(db.query("orders").count().unwrap(), 1);
+        assert!(matches!(db.commit(), Err(OxidbError::NoTransaction)));
+
+        // Een closure die panikeert wordt ook teruggedraaid
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+            db.transaction(|tx| -> Result<()> {
+                tx.insert("orders", Record::new(3).with("item", 1))?;
+                panic!("boom")
+            })
+        }));
+        assert!(panicked.is_err());
+        assert!(db.get("orders", 3).unwrap().is_none());
+        db.begin().unwrap();
+        db.rollback().unwrap();
+
+        // Gecommitte transacties worden na een crash volledig teruggehaald
+        drop(db);
+        let mut db2 = MiniDB::new(test_path).unwrap();
+        db2.load().unwrap();
+        assert_eq!(db2.query("orders").count().unwrap(), 1);
+        assert_eq!(db2.get("stock", 1).unwrap().unwrap().get_i64("count").unwrap(), 0);
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_shared_handle_across_threads() {
+        let test_path = "./test_data_26";
+        cleanup_test_dir(test_path);
+
+        fn assert_send_sync<T: Send + Sync + Clone>() {}
+        assert_send_sync::<SharedDb>();
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.create_table("hits").unwrap();
+        let shared = SharedDb::new(db);
+
+        let workers: Vec<_> = (0..4)
+            .map(|t| {
+                let shared = shared.clone();
+                std::thread::spawn(move || {
+                    for i in 0..25 {
+                        shared.write(|db| db.insert("hits", Record::new(t * 100 + i + 1))).unwrap();
+                        shared.read(|db| db.query("hits").count()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        // Een save terwijl er nog geschreven en gelezen wordt
+        shared.save().unwrap();
+        workers.into_iter().for_each(|w| w.join().unwrap());
+
+        assert_eq!(shared.read(|db| db.query("hits").count()).unwrap(), 100);
+        assert!(shared.get("hits", 101).unwrap().is_some());
+        shared.transaction(|tx| tx.delete("hits", 1).map(|_| ())).unwrap();
+
+        // Een transactie blijft niet openstaan na een write
+        let err = shared.write(|db| {
+            db.begin()?;
+            db.delete("hits", 2)
+        });
+        assert!(matches!(err, Err(OxidbError::TransactionActive)));
+        assert!(shared.get("hits", 2).unwrap().is_some());
+
+        // Automatisch opslaan gaat via dezelfde weg als save
+        shared.write(|db| {
+            db.set_auto_save(Some(Duration::ZERO));
+            db.upsert("hits", Record::new(101))
+        }).unwrap();
+        assert!(shared.read(|db| db.dirty_tables().is_empty()));
+        shared.save().unwrap();
+
+        let db = shared.into_inner().ok().unwrap();
+        assert!(db.dirty_tables().is_empty());
+        drop(db);
+        let mut db2 = MiniDB::new(test_path).unwrap();
+        db2.load().unwrap();
+        assert_eq!(db2.query("hits").count().unwrap(), 99);
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_directory_lock_and_read_only_mode() {
+        let test_path = "./test_data_27";
+        cleanup_test_dir(test_path);
+
+        let mut writer = MiniDB::new(test_path).unwrap();
+        let err = MiniDB::new(test_path).err().unwrap();
+        assert!(matches!(err, OxidbError::Locked { .. }));
+        assert_eq!(err.path(), Some(&PathBuf::from(test_path)));
+
+        writer.create_table("users").unwrap();
+        writer.insert("users", Record::new(1).with("name", "Stan")).unwrap();
+        writer.save().unwrap();
+        writer.insert("users", Record::new(2).with("name", "Eva")).unwrap();
+
+        // Een lezer kan naast de schrijver openen en ziet ook wat nog in de log staat
+        let mut reader = MiniDB::open_read_only(test_path).unwrap();
+        reader.load().unwrap();
+        assert!(reader.is_read_only());
+        assert_eq!(reader.query("users").count().unwrap(), 2);
+        assert!(matches!(reader.insert("users", Record::new(3)), Err(OxidbError::ReadOnly)));
+        assert!(matches!(reader.save(), Err(OxidbError::ReadOnly)));
+        assert!(Path::new(test_path).join(wal::WAL_FILE).exists());
+
+        // Na het sluiten van de schrijver is de map weer vrij
+        drop(writer);
+        let mut writer2 = MiniDB::new(test_path).unwrap();
+        writer2.load().unwrap();
+        assert_eq!(writer2.query("users").count().unwrap(), 2);
+        assert!(MiniDB::open_read_only("./test_data_missing").is_err());
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_read_only_load_during_checkpoints() {
+        let test_path = "./test_data_38";
+        cleanup_test_dir(test_path);
+
+        let mut writer = MiniDB::new(test_path).unwrap();
+        writer.set_sync_policy(SyncPolicy::Never);
+        writer.create_table("hits").unwrap();
+        let done = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let handle = {
+            let done = done.clone();
+            std::thread::spawn(move || {
+                for id in 1..=200 {
+                    writer.insert("hits", Record::new(id)).unwrap();
+                    done.store(id, std::sync::atomic::Ordering::SeqCst);
+                    if id % 3 == 0 {
+                        writer.save().unwrap();
+                    }
+                }
+            })
+        };
+
+        // Een lezer mist nooit wat al gelogd was voordat hij begon te laden,
+        // ook niet als er intussen een checkpoint de log leegmaakt
+        while !handle.is_finished() {
+            let logged = done.load(std::sync::atomic::Ordering::SeqCst);
+            let mut reader = MiniDB::open_read_only(test_path).unwrap();
+            assert!(reader.load().unwrap().is_empty());
+            assert!(reader.query("hits").count().unwrap() as u64 >= logged);
+        }
+        handle.join().unwrap();
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_read_only_load_never_sees_half_a_transaction() {
+        let test_path = "./test_data_42";
+        cleanup_test_dir(test_path);
+
+        let mut writer = MiniDB::new(test_path).unwrap();
+        writer.set_sync_policy(SyncPolicy::Never);
+        writer.create_table("orders").unwrap();
+        writer.create_table("stock").unwrap();
+        writer.save().unwrap();
+        let handle = std::thread::spawn(move || {
+            for id in 1..=200 {
+                writer
+                    .transaction(|tx| {
+                        tx.insert("orders", Record::new(id))?;
+                        tx.insert("stock", Record::new(id))
+                    })
+                    .unwrap();
+                writer.save().unwrap();
+            }
+        });
+
+        // Een checkpoint tussen het lezen van de twee tabellen mag niet
+        // zichtbaar worden, ook niet als de log voor en na leeg is
+        while !handle.is_finished() {
+            let mut reader = MiniDB::open_read_only(test_path).unwrap();
+            assert!(reader.load().unwrap().is_empty());
+            assert_eq!(reader.query("orders").ids().unwrap(), reader.query("stock").ids().unwrap());
+        }
+        handle.join().unwrap();
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_snapshots_are_isolated_from_later_writes() {
+        let test_path = "./test_data_28";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        seed_users(&mut db);
+        db.create_index("users", "role", IndexKind::Hash).unwrap();
+
+        let snap = db.snapshot();
+        let old_users = Arc::downgrade(&db.tables["users"]);
+        db.insert("users", Record::new(10).with("name", "Nieuw").with("role", "Admin")).unwrap();
+        db.update("users", 1, HashMap::from([("role".to_string(), Value::from("User"))])).unwrap();
+        db.delete("users", 2).unwrap();
+        db.drop_table("users").unwrap();
+        db.create_table("users").unwrap();
+
+        // De snapshot ziet nog precies de oude stand, ook via de index
+        let admins = || field("role").eq("Admin");
+        assert_eq!(snap.query("users").filter(admins()).ids().unwrap(), vec![1, 3]);
+        assert_eq!(snap.get("users", 2).unwrap().unwrap().get_str("name").unwrap(), "Eva");
+        assert_eq!(snap.query("users").count().unwrap(), 4);
+        assert_eq!(db.query("users").count().unwrap(), 0);
+
+        // Oude versies worden vrijgegeven zodra geen snapshot ze meer vasthoudt
+        let snap2 = db.snapshot();
+        assert!(old_users.upgrade().is_some());
+        drop(snap);
+        assert!(old_users.upgrade().is_none());
+        db.insert("users", Record::new(1)).unwrap();
+        assert_eq!(snap2.query("users").count().unwrap(), 0);
+        drop(snap2);
+        assert_eq!(Arc::strong_count(&db.tables["users"]), 1);
+
+        // Een snapshot tijdens een transactie ziet alleen gecommitte wijzigingen
+        db.begin().unwrap();
+        db.insert("users", Record::new(2)).unwrap();
+        db.create_table("audit").unwrap();
+        let snap3 = db.snapshot();
+        db.rollback().unwrap();
+        assert_eq!(snap3.query("users").ids().unwrap(), vec![1]);
+        assert!(!snap3.table_exists("audit"));
+
+        // Via een gedeelde handle: lezen uit de snapshot terwijl er geschreven wordt
+        drop(db);
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.create_table("hits").unwrap();
+        let shared = SharedDb::new(db);
+        let snap = shared.snapshot();
+        let writer = {
+            let shared = shared.clone();
+            std::thread::spawn(move || {
+                for id in 1..=50 {
+                    shared.write(|db| db.insert("hits", Record::new(id))).unwrap();
+                }
+            })
+        };
+        assert_eq!(snap.query("hits").count().unwrap(), 0);
+        writer.join().unwrap();
+        assert_eq!(snap.query("hits").count().unwrap(), 0);
+        assert_eq!(shared.snapshot().query("hits").count().unwrap(), 50);
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_binary_format_and_conversion() {
+        let test_path = "./test_data_29";
+        cleanup_test_dir(test_path);
+
+        let record = Record::new(1)
+            .with("name", "Laptop")
+            .with("weight", f64::INFINITY)
+            .with("thumbnail", vec![0x89u8, b'P', b'N', b'G'])
+            .with("added", Value::Timestamp(1_700_000_000_000))
+            .with("meta", std::collections::BTreeMap::from([("$bytes".to_string(), Value::from("geen tag"))]));
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.create_table("products").unwrap();
+        db.create_index("products", "name", IndexKind::Ordered).unwrap();
+        db.insert("products", record.clone()).unwrap();
+        for id in 2..=50 {
+            db.insert("products", Record::new(id).with("name", format!("product {}", id)).with("price", id as i64)).unwrap();
+        }
+        db.save().unwrap();
+        let json_size = fs::metadata(Path::new(test_path).join("products.json")).unwrap().len();
+
+        // Overschakelen herschrijft alle tabellen en ruimt de JSON-bestanden op
+        db.set_format(Format::Binary);
+        db.save().unwrap();
+        let binary_file = Path::new(test_path).join("products.oxdb");
+        let bytes = fs::read(&binary_file).unwrap();
+        assert_eq!(&bytes[..6], b"OXDB\x01\x00");
+        assert!((bytes.len() as u64) < json_size / 2, "{} vs {}", bytes.len(), json_size);
+        assert!(!Path::new(test_path).join("products.json").exists());
+
+        drop(db);
+        let mut db2 = MiniDB::new(test_path).unwrap();
+        db2.load().unwrap();
+        assert_eq!(db2.get("products", 1).unwrap().unwrap(), &record);
+        assert_eq!(db2.indexes("products").unwrap().len(), 1);
+
+        // Terug naar JSON met de converter
+        drop(db2);
+        MiniDB::convert(test_path, Format::Json).unwrap();
+        assert!(!binary_file.exists());
+        let mut db3 = MiniDB::new(test_path).unwrap();
+        db3.load().unwrap();
+        assert_eq!(db3.get("products", 1).unwrap().unwrap(), &record);
+        assert_eq!(db3.query("products").count().unwrap(), 50);
+
+        // Een bestand uit een nieuwere versie wordt geweigerd in plaats van verkeerd gelezen
+        drop(db3);
+        fs::write(&binary_file, b"OXDB\x09\x00").unwrap();
+        fs::remove_file(Path::new(test_path).join("products.json")).unwrap();
+        let mut db4 = MiniDB::new(test_path).unwrap();
+        let problems = db4.load().unwrap();
+        assert!(matches!(problems[..], [OxidbError::CorruptTable { .. }]), "{:?}", problems);
+        assert_eq!(problems[0].path(), Some(&binary_file));
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_checksums_catch_damaged_files() {
+        let test_path = "./test_data_30";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        seed_users(&mut db);
+        db.create_table("orders").unwrap();
+        db.insert("orders", Record::new(1).with("item", "boek")).unwrap();
+        db.save().unwrap();
+        db.insert("orders", Record::new(2).with("item", "pen")).unwrap();
+        let report = db.verify().unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert_eq!(report.tables, vec!["orders", "users"]);
+        assert_eq!(report.wal_entries, 1);
+
+        // Een handmatige aanpassing die nog steeds geldige JSON oplevert
+        let users_file = Path::new(test_path).join("users.json");
+        let edited = fs::read_to_string(&users_file).unwrap().replace("Piet", "Kees");
+        fs::write(&users_file, edited).unwrap();
+        // En een afgebroken append in de log
+        let wal_file = Path::new(test_path).join(wal::WAL_FILE);
+        let mut wal_bytes = fs::read(&wal_file).unwrap();
+        let intact = wal_bytes.len() as u64;
+        wal_bytes.extend_from_slice(&[9, 0, 0, 0, 0, 0]);
+        fs::write(&wal_file, wal_bytes).unwrap();
+
+        let report = db.verify().unwrap();
+        assert_eq!(report.tables, vec!["orders"]);
+        assert_eq!(report.problems.len(), 2);
+        assert_eq!(report.problems[0].path(), Some(&users_file));
+        assert!(report.problems[0].to_string().contains("checksum mismatch"));
+        assert!(matches!(&report.problems[1], OxidbError::CorruptWal { offset, .. } if *offset == intact));
+
+        // Load slaat alleen de kapotte tabel over
+        drop(db);
+        let mut db2 = MiniDB::new(test_path).unwrap();
+        let problems = db2.load().unwrap();
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].table(), Some("users"));
+        assert!(!db2.table_exists("users"));
+        assert_eq!(db2.query("orders").count().unwrap(), 2);
+
+        // Ook het binaire formaat heeft een checksum
+        db2.set_format(Format::Binary);
+        db2.save().unwrap();
+        let orders_file = Path::new(test_path).join("orders.oxdb");
+        let mut bytes = fs::read(&orders_file).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+        fs::write(&orders_file, bytes).unwrap();
+        let report = db2.verify().unwrap();
+        assert!(report.problems.iter().any(|p| p.path() == Some(&orders_file)));
+
+        // Tabelbestanden van voor de checksums laden nog gewoon
+        fs::write(Path::new(test_path).join("legacy.json"), r#"{"name": "legacy", "records": {}}"#).unwrap();
+        assert!(db2.verify().unwrap().tables.contains(&"legacy".to_string()));
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_compression_per_database_and_per_table() {
+        let test_path = "./test_data_31";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        for table in ["logs", "notes"] {
+            db.create_table(table).unwrap();
+            for id in 1..=200 {
+                let line = format!("GET /api/v1/users?page={} HTTP/1.1 200 OK", id % 3);
+                db.insert(table, Record::new(id).with("line", line)).unwrap();
+            }
+        }
+        db.save().unwrap();
+        let file_size = |name: &str| fs::metadata(Path::new(test_path).join(name)).unwrap().len();
+        let plain = file_size("logs.json");
+        let notes = file_size("notes.json");
+
+        // Alleen de logs comprimeren; het bestand blijft logs.json, met header
+        db.set_table_compression("logs", Some(Compression::Deflate)).unwrap();
+        assert_eq!(db.dirty_tables(), vec!["logs"]);
+        db.save().unwrap();
+        assert!(file_size("logs.json") < plain / 10, "{} vs {}", file_size("logs.json"), plain);
+        assert_eq!(&fs::read(Path::new(test_path).join("logs.json")).unwrap()[..6], b"OXDB\x01\x03");
+        assert_eq!(file_size("notes.json"), notes);
+
+        // Een gemengde map laadt gewoon, en de instelling per tabel blijft bewaard
+        drop(db);
+        let mut db2 = MiniDB::new(test_path).unwrap();
+        assert!(db2.load().unwrap().is_empty());
+        assert_eq!(db2.query("logs").count().unwrap(), 200);
+        assert_eq!(db2.get("logs", 7).unwrap().unwrap().data, db2.get("notes", 7).unwrap().unwrap().data);
+        assert_eq!(db2.tables["logs"].compression, Some(Compression::Deflate));
+
+        // Compressie voor de hele database, in het binaire formaat
+        db2.set_format(Format::Binary);
+        db2.set_compression(Compression::Deflate);
+        db2.set_table_compression("logs", None).unwrap();
+        db2.save().unwrap();
+        assert_eq!(&fs::read(Path::new(test_path).join("notes.oxdb")).unwrap()[..6], b"OXDB\x01\x01");
+        drop(db2);
+        let mut db3 = MiniDB::new(test_path).unwrap();
+        db3.load().unwrap();
+        assert_eq!(db3.query("notes").count().unwrap(), 200);
+        assert!(db3.verify().unwrap().is_ok());
+        drop(db3);
+
+        // Een bestand dat naar meer uitpakt dan de header belooft, is corrupt
+        let mut payload = 16u64.to_le_bytes().to_vec();
+        payload.extend_from_slice(&miniz_oxide::deflate::compress_to_vec(&vec![0; 1 << 20], 6));
+        let mut bytes = b"OXDB\x01\x01".to_vec();
+        bytes.extend_from_slice(&checksum::crc32(&payload).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        fs::write(Path::new(test_path).join("bomb.oxdb"), &bytes).unwrap();
+        let mut db4 = MiniDB::new(test_path).unwrap();
+        let problems = db4.load().unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(&problems[0], OxidbError::CorruptTable { reason, .. } if reason.contains("more than 16 bytes")), "{}", problems[0]);
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_encryption_wrong_key_and_rotation() {
+        let test_path = "./test_data_32";
+        cleanup_test_dir(test_path);
+        let key = |b: u8| EncryptionKey::new([b; 32]);
+        let users_file = Path::new(test_path).join("users.json");
+        let wal_file = Path::new(test_path).join(wal::WAL_FILE);
+        let contains = |path: &Path, needle: &str| {
+            fs::read(path).unwrap().windows(needle.len()).any(|w| w == needle.as_bytes())
+        };
+
+        // Een bestaande, onversleutelde tabel wordt bij de volgende save versleuteld
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.create_table("users").unwrap();
+        db.insert("users", Record::new(1).with("bsn", "123456782")).unwrap();
+        db.save().unwrap();
+        drop(db);
+        assert!(contains(&users_file, "123456782"));
+
+        let mut db = MiniDB::new(test_path).unwrap().with_key(key(1));
+        assert!(db.load().unwrap().is_empty());
+        assert_eq!(db.dirty_tables(), vec!["users"]);
+        db.save().unwrap();
+        assert!(!contains(&users_file, "123456782"));
+        db.insert("users", Record::new(2).with("bsn", "987654321")).unwrap();
+        assert!(!contains(&wal_file, "987654321"));
+        drop(db);
+
+        // Verkeerde of ontbrekende sleutel: nette fouten met het pad erbij
+        let db = MiniDB::open_read_only(test_path).unwrap().with_key(key(2));
+        let report = db.verify().unwrap();
+        assert!(matches!(&report.problems[..], [OxidbError::WrongKey { .. }, OxidbError::WrongKey { .. }]));
+        assert_eq!(report.problems[0].path(), Some(&users_file));
+        assert_eq!(report.problems[1].path(), Some(&wal_file));
+        let mut db = MiniDB::open_read_only(test_path).unwrap();
+        assert!(matches!(db.load(), Err(OxidbError::MissingKey { .. })));
+
+        // Met de juiste sleutel is alles er, ook wat nog in de log stond
+        let mut db = MiniDB::new(test_path).unwrap().with_key(key(1));
+        db.load().unwrap();
+        assert_eq!(db.get("users", 2).unwrap().unwrap().get_str("bsn").unwrap(), "987654321");
+
+        // Sleutelrotatie herschrijft alle tabellen en leegt de log
+        db.rotate_key(Some(key(3))).unwrap();
+        assert!(!wal_file.exists());
+        drop(db);
+        let db = MiniDB::open_read_only(test_path).unwrap().with_key(key(1));
+        assert!(matches!(&db.verify().unwrap().problems[..], [OxidbError::WrongKey { .. }]));
+        let mut db = MiniDB::new(test_path).unwrap().with_key(key(3));
+        assert!(db.load().unwrap().is_empty());
+        assert_eq!(db.query("users").count().unwrap(), 2);
+
+        // Een onderbroken rotatie: bestanden onder de oude sleutel blijven leesbaar via with_old_key
+        db.create_table("orders").unwrap();
+        db.save().unwrap();
+        drop(db);
+        let mut db = MiniDB::new(test_path).unwrap().with_key(key(4)).with_old_key(key(3));
+        assert!(db.load().unwrap().is_empty());
+        assert_eq!(db.dirty_tables(), vec!["orders", "users"]);
+        db.save().unwrap();
+        drop(db);
+        let db = MiniDB::open_read_only(test_path).unwrap().with_key(key(4));
+        assert!(db.verify().unwrap().is_ok());
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+    struct User {
+        id: u64,
+        name: String,
+        role: Role,
+        age: Option<i64>,
+        tags: Vec<String>,
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+    enum Role {
+        Admin,
+        User,
+    }
+
+    impl OxidbRecord for User {
+        const TABLE: &'static str = "users";
+
+        fn id(&self) -> u64 {
+            self.id
+        }
+    }
+
+    #[test]
+    fn test_typed_table() {
+        let test_path = "./test_data_33";
+        cleanup_test_dir(test_path);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.create_table("users").unwrap();
+        let stan = User { id: 1, name: "Stan".into(), role: Role::Admin, age: Some(35), tags: vec!["ops".into()] };
+        let mut users = db.table::<User>("users");
+        users.insert(&stan).unwrap();
+        assert!(matches!(users.insert(&stan), Err(OxidbError::DuplicateId { id: 1, .. })));
+        let eva_id = users.insert_new(&User { id: 0, name: "Eva".into(), role: Role::User, age: None, tags: vec![] }).unwrap();
+        assert_eq!(eva_id, 2);
+        assert_eq!(users.get(1).unwrap().unwrap(), stan);
+        assert_eq!(users.get(2).unwrap().unwrap().id, 2);
+
+        // Het id staat alleen in het record, niet nog eens tussen de velden
+        let record = db.get("users", 1).unwrap().unwrap();
+        assert_eq!(record.get("id"), None);
+        assert_eq!(record.get_str("role").unwrap(), "Admin");
+        assert_eq!(record.get("age"), Some(&Value::Int(35)));
+
+        // Ongetypeerd schrijven, getypeerd lezen en andersom
+        db.insert("users", Record::new(3).with("name", "Piet").with("role", "Admin").with("age", Value::Null)
+            .with("tags", Value::List(vec![]))).unwrap();
+        let mut users = db.table::<User>("users");
+        assert_eq!(users.get(3).unwrap().unwrap().age, None);
+        users.update(&User { id: 3, name: "Piet".into(), role: Role::Admin, age: Some(52), tags: vec![] }).unwrap();
+        assert!(matches!(users.update(&User { id: 9, ..stan.clone() }), Err(OxidbError::RecordNotFound { id: 9, .. })));
+        let admins = users.query().filter(field("role").eq("Admin")).order_by(desc("age")).records().unwrap();
+        assert_eq!(admins.iter().map(|u| u.name.as_str()).collect::<Vec<_>>(), ["Piet", "Stan"]);
+        let page = users.query().order_by(asc("name")).page(2).unwrap();
+        assert_eq!(page.records.iter().map(|u| u.id).collect::<Vec<_>>(), [2, 3]);
+        assert!(page.next.is_some());
+
+        // Een record dat niet in het type past geeft een nette fout met tabel en id
+        db.insert("users", Record::new(4).with("name", "Anna")).unwrap();
+        let mut users = db.table::<User>("users");
+        let err = users.get(4).unwrap_err();
+        assert!(matches!(&err, OxidbError::Conversion { id: Some(4), .. }));
+        assert_eq!(err.table(), Some("users"));
+        assert!(users.query().records().is_err());
+        assert_eq!(users.delete(3).unwrap().age, Some(52));
+
+        // Getypeerde wijzigingen gaan via dezelfde log en bestanden
+        db.save().unwrap();
+        drop(db);
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.load().unwrap();
+        assert_eq!(db.table::<User>("users").get(1).unwrap().unwrap().tags, ["ops"]);
+
+        // Willekeurige ids passen niet in een i64 maar wel in het getypeerde id
+        db.create_table_with_id_strategy("random", IdStrategy::Random).unwrap();
+        let mut random = db.table::<User>("random");
+        let big = User { id: u64::MAX - 1, ..stan };
+        random.insert(&big).unwrap();
+        assert_eq!(random.get(u64::MAX - 1).unwrap().unwrap(), big);
+        assert!(matches!(db.table::<User>("nope").get(1), Err(OxidbError::TableNotFound { .. })));
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize, OxidbRecord, Clone, Debug, PartialEq)]
+    #[oxidb(table = "accounts", unique(owner, label))]
+    struct Account {
+        #[oxidb(id)]
+        number: u64,
+        #[oxidb(index = "hash")]
+        owner: String,
+        label: String,
+        #[oxidb(unique)]
+        #[serde(rename = "iban_code")]
+        iban: String,
+        balance: f64,
+        #[oxidb(type = "timestamp", index)]
+        opened: i64,
+        #[oxidb(type = "bytes")]
+        avatar: Vec<u8>,
+        closed: Option<bool>,
+        #[serde(skip)]
+        cache: u32,
+    }
+
+    #[test]
+    fn test_derived_record() {
+        let test_path = "./test_data_34";
+        cleanup_test_dir(test_path);
+
+        // De derive levert id, tabelnaam, schema, indexen en unieke sleutels
+        assert_eq!(Account::TABLE, "accounts");
+        assert_eq!(Account::ID_FIELD, "number");
+        let schema = Account::schema().unwrap();
+        let names: Vec<&str> = schema.columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["owner", "label", "iban_code", "balance", "opened", "avatar", "closed"]);
+        assert_eq!(schema.get_column("balance").unwrap(), &Column::new("balance", ColumnType::Float));
+        assert_eq!(schema.get_column("closed").unwrap(), &Column::new("closed", ColumnType::Bool).nullable());
+        assert_eq!(Account::indexes(), [
+            IndexDef { field: "owner".into(), kind: IndexKind::Hash },
+            IndexDef { field: "opened".into(), kind: IndexKind::Ordered },
+        ]);
+        let unique: Vec<Vec<String>> = Account::unique().into_iter().map(|c| c.fields).collect();
+        assert_eq!(unique, [vec!["iban_code".to_string()], vec!["owner".into(), "label".into()]]);
+
+        let mut db = MiniDB::new(test_path).unwrap();
+        let account = Account {
+            number: 7,
+            owner: "Stan".into(),
+            label: "Sparen".into(),
+            iban: "NL01".into(),
+            balance: 10.0,
+            opened: 1_700_000_000_000,
+            avatar: vec![1, 2, 3],
+            closed: None,
+            cache: 0,
+        };
+        let mut accounts = db.open_table::<Account>().unwrap();
+        accounts.insert(&account).unwrap();
+        let copy = Account { number: 8, label: "Lopend".into(), ..account.clone() };
+        assert!(matches!(accounts.insert(&copy), Err(OxidbError::ConstraintViolation { .. })));
+        assert_eq!(accounts.get(7).unwrap().unwrap(), account);
+        assert_eq!(db.indexes("accounts").unwrap().len(), 2);
+
+        // Tijdstempels en bytes worden volgens het schema opgeslagen
+        let record = db.get("accounts", 7).unwrap().unwrap();
+        assert_eq!(record.get("opened"), Some(&Value::Timestamp(1_700_000_000_000)));
+        assert_eq!(record.get_bytes("avatar").unwrap(), [1, 2, 3]);
+
+        // Niet-eindige floats blijven floats in plaats van null te worden
+        let overdrawn = Account { number: 9, label: "Rood".into(), iban: "NL02".into(), balance: f64::NEG_INFINITY, ..account.clone() };
+        db.open_table::<Account>().unwrap().insert(&overdrawn).unwrap();
+        assert_eq!(db.get("accounts", 9).unwrap().unwrap().get("balance"), Some(&Value::Float(f64::NEG_INFINITY)));
+        db.save().unwrap();
+        drop(db);
+
+        // Bij het openen wordt het opgeslagen schema met het type vergeleken
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.load().unwrap();
+        assert_eq!(db.open_table::<Account>().unwrap().get(7).unwrap().unwrap().balance, 10.0);
+        assert_eq!(db.open_table::<Account>().unwrap().get(9).unwrap().unwrap(), overdrawn);
+        let unknown = Account { balance: f64::NAN, ..overdrawn };
+        db.open_table::<Account>().unwrap().update(&unknown).unwrap();
+        assert!(db.open_table::<Account>().unwrap().get(9).unwrap().unwrap().balance.is_nan());
+        db.drop_index("accounts", "owner").unwrap();
+        let err = db.open_table::<Account>().err().unwrap();
+        assert!(matches!(&err, OxidbError::SchemaMismatch { table, .. } if table == "accounts"));
+        assert!(err.to_string().contains("index on 'owner'"), "{}", err);
+
+        // Een bestaande tabel met een ander schema; de tabelnaam volgt uit de structnaam
+        #[derive(serde::Serialize, serde::Deserialize, OxidbRecord)]
+        struct PersonRow {
+            id: u64,
+            name: String,
+        }
+        db.create_table_with_schema("person_row", Schema::new().column(Column::new("name", ColumnType::Int))).unwrap();
+        let err = db.open_table::<PersonRow>().err().unwrap();
+        assert!(err.to_string().contains("column 'name'"), "{}", err);
+
+        // Een reeks hoofdletters is één woord, en rename_all geldt voor alle velden
+        #[derive(serde::Serialize, serde::Deserialize, OxidbRecord, Debug, PartialEq)]
+        #[serde(rename_all = "camelCase")]
+        struct HTTPServer {
+            #[oxidb(id)]
+            server_id: u64,
+            host_name: String,
+            #[serde(rename = "PORT")]
+            port_number: i64,
+        }
+        assert_eq!(HTTPServer::TABLE, "http_server");
+        assert_eq!(HTTPServer::ID_FIELD, "serverId");
+        let names: Vec<String> = HTTPServer::schema().unwrap().columns.into_iter().map(|c| c.name).collect();
+        assert_eq!(names, ["hostName", "PORT"]);
+        let server = HTTPServer { server_id: 1, host_name: "db1".into(), port_number: 5432 };
+        db.open_table::<HTTPServer>().unwrap().insert(&server).unwrap();
+        assert_eq!(db.open_table::<HTTPServer>().unwrap().get(1).unwrap().unwrap(), server);
+        assert_eq!(db.get("http_server", 1).unwrap().unwrap().get_str("hostName").unwrap(), "db1");
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_open_options() {
+        let test_path = "./test_data_35";
+        cleanup_test_dir(test_path);
+
+        // Zonder create bestaat de map niet en faalt het openen
+        let err = OpenOptions::new().create(false).open(test_path).err().unwrap();
+        assert!(matches!(err, OxidbError::Io { .. }));
+        assert!(!Path::new(test_path).exists());
+
+        // open laadt meteen, een vergeten load is dus niet meer mogelijk
+        let mut db = MiniDB::open(test_path).unwrap();
+        db.create_table("users").unwrap();
+        db.insert("users", Record::new(1).with("name", "Stan")).unwrap();
+        drop(db);
+        let db = OpenOptions::new().create(false).open(test_path).unwrap();
+        assert_eq!(db.get("users", 1).unwrap().unwrap().get_str("name").unwrap(), "Stan");
+        drop(db);
+        let db = OpenOptions::new().load(false).open(test_path).unwrap();
+        assert!(!db.table_exists("users"));
+        drop(db);
+
+        // Read-only en formaat
+        let mut db = OpenOptions::new().read_only(true).format(Format::Binary).open(test_path).unwrap();
+        assert!(db.is_read_only());
+        assert!(matches!(db.create_table("orders"), Err(OxidbError::ReadOnly)));
+        drop(db);
+
+        // Automatisch opslaan: met interval 0 wordt na elke wijziging een checkpoint gemaakt
+        let mut db = OpenOptions::new()
+            .format(Format::Binary)
+            .sync_policy(SyncPolicy::Never)
+            .auto_save(Duration::ZERO)
+            .open(test_path)
+            .unwrap();
+        db.insert("users", Record::new(2).with("name", "Eva")).unwrap();
+        assert!(db.dirty_tables().is_empty());
+        assert!(Path::new(test_path).join("users.oxdb").exists());
+        assert!(!Path::new(test_path).join("users.json").exists());
+        db.transaction(|tx| tx.insert("users", Record::new(3).with("name", "Piet"))).unwrap();
+        assert!(db.dirty_tables().is_empty());
+
+        // Een mislukte automatische checkpoint houdt andere wijzigingen niet
+        // tegen; de fout is op te halen
+        let users_file = Path::new(test_path).join("users.oxdb");
+        fs::remove_file(&users_file).unwrap();
+        fs::create_dir_all(users_file.join("in_de_weg")).unwrap();
+        let eva = Record::new(2).with("name", "Eva");
+        db.upsert("users", eva.clone()).unwrap();
+        assert_eq!(db.dirty_tables(), vec!["users"]);
+        db.upsert("users", eva.clone()).unwrap();
+        assert!(matches!(db.take_auto_save_error(), Some(OxidbError::Io { .. })));
+        assert!(db.take_auto_save_error().is_none());
+        fs::remove_dir_all(&users_file).unwrap();
+        db.upsert("users", eva).unwrap();
+        assert!(db.dirty_tables().is_empty());
+        assert!(db.take_auto_save_error().is_none());
+        assert!(users_file.is_file());
+        drop(db);
+
+        // Geheugenlimiet: groeien faalt, krimpen mag altijd
+        let mut db = OpenOptions::new().format(Format::Binary).open(test_path).unwrap();
+        let usage = db.memory_usage();
+        assert!(usage > 0);
+        db.set_memory_limit(Some(usage + 10));
+        let big = Record::new(4).with("name", "x".repeat(100));
+        assert!(matches!(db.insert("users", big), Err(OxidbError::MemoryLimit { .. })));
+        db.delete("users", 3).unwrap();
+        assert!(db.memory_usage() < usage);
+        drop(db);
+        let err = OpenOptions::new().memory_limit(10).open(test_path).err().unwrap();
+        assert!(matches!(err, OxidbError::MemoryLimit { limit: 10, .. }));
+
+        // In het geheugen: begint met wat er op schijf staat, maar schrijft er nooit naar
+        let files = |path: &str| {
+            let mut names: Vec<String> = fs::read_dir(path).unwrap().map(|e| e.unwrap().file_name().into_string().unwrap()).collect();
+            names.sort();
+            names
+        };
+        let before = files(test_path);
+        let mut db = OpenOptions::new().in_memory(true).format(Format::Binary).open(test_path).unwrap();
+        assert_eq!(db.query("users").count().unwrap(), 2);
+        db.create_table("orders").unwrap();
+        db.drop_table("users").unwrap();
+        db.save().unwrap();
+        assert_eq!(files(test_path), before);
+        let _other = MiniDB::new(test_path).unwrap();
+        let db = OpenOptions::new().in_memory(true).open("./test_data_35_missing").unwrap();
+        assert!(db.list_tables().is_empty());
+        assert!(!Path::new("./test_data_35_missing").exists());
+
+        cleanup_test_dir(test_path);
+    }
+
+    #[test]
+    fn test_in_memory_database_persist_and_copy() {
+        let test_path = "./test_data_36";
+        cleanup_test_dir(test_path);
+
+        // Dezelfde API, maar zonder bestanden
+        let mut db = MiniDB::in_memory();
+        assert!(db.is_in_memory());
+        db.create_table("users").unwrap();
+        db.create_index("users", "name", IndexKind::Hash).unwrap();
+        db.insert("users", Record::new(1).with("name", "Stan")).unwrap();
+        db.transaction(|tx| tx.insert("users", Record::new(2).with("name", "Eva"))).unwrap();
+        assert_eq!(db.dirty_tables(), vec!["users"]);
+        db.save().unwrap();
+        assert!(db.dirty_tables().is_empty());
+        assert!(db.load().unwrap().is_empty());
+        assert!(db.verify().unwrap().is_ok());
+        assert_eq!(db.query("users").count().unwrap(), 2);
+
+        // Een kopie tijdens een transactie bevat alleen wat al vastgelegd is
+        db.begin().unwrap();
+        db.insert("users", Record::new(9)).unwrap();
+        db.create_table("drafts").unwrap();
+        let during = db.in_memory_copy();
+        db.rollback().unwrap();
+        assert_eq!(during.query("users").ids().unwrap(), [1, 2]);
+        assert!(!during.table_exists("drafts"));
+
+        // Een kopie in het geheugen staat los van het origineel
+        let mut copy = db.in_memory_copy();
+        copy.delete("users", 1).unwrap();
+        copy.create_table("orders").unwrap();
+        assert_eq!(db.query("users").ids().unwrap(), [1, 2]);
+        assert!(!db.table_exists("orders"));
+        assert_eq!(copy.query("users").filter(field("name").eq("Eva")).ids().unwrap(), [2]);
+
+        // Later alsnog naar schijf, daarna gedraagt de database zich als een gewone
+        assert!(!Path::new(test_path).exists());
+        db.set_format(Format::Binary);
+        db.persist(test_path).unwrap();
+        assert!(!db.is_in_memory());
+        assert!(Path::new(test_path).join("users.oxdb").exists());
+        db.insert("users", Record::new(3).with("name", "Piet")).unwrap();
+        assert!(matches!(MiniDB::new(test_path), Err(OxidbError::Locked { .. })));
+        drop(db);
+        // Zonder opgegeven formaat houdt hij het formaat van de bestanden
+        let mut db = MiniDB::new(test_path).unwrap();
+        db.load().unwrap();
+        assert_eq!(db.format(), Format::Binary);
+        assert_eq!(db.query("users").filter(field("name").eq("Piet")).ids().unwrap(), [3]);
+        assert_eq!(db.indexes("users").unwrap().len(), 1);
+        db.delete("users", 3).unwrap();
+        db.save().unwrap();
+        assert!(Path::new(test_path).join("users.oxdb").exists());
+        assert!(!Path::new(test_path).join("users.json").exists());
+        drop(db);
+
+        // Een map met al een database erin wordt niet overschreven
+        let err = copy.persist(test_path).unwrap_err();
+        assert!(matches!(&err, OxidbError::Io { source, .. } if source.kind() == std::io::ErrorKind::AlreadyExists));
+        assert!(copy.is_in_memory());
+
+        // Als het schrijven halverwege mislukt, blijft de database zoals hij was
+        let blocked_path = "./test_data_36_blocked";
+        cleanup_test_dir(blocked_path);
+        fs::create_dir_all(Path::new(blocked_path).join("orders.json.tmp")).unwrap();
+        assert!(matches!(copy.persist(blocked_path), Err(OxidbError::Io { .. })));
+        assert!(copy.is_in_memory());
+        assert_eq!(copy.dirty_tables(), vec!["orders", "users"]);
+        copy.insert("orders", Record::new(1)).unwrap();
+        copy.save().unwrap();
+        assert!(!Path::new(blocked_path).join("orders.json").exists());
+        drop(MiniDB::new(blocked_path).unwrap());
+        cleanup_test_dir(blocked_path);
+
+        cleanup_test_dir(test_path);
+    }
+}
@@ -0,0 +1,184 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::time::Duration;
+use crate::crypto::EncryptionKey;
+use crate::error::{OxidbError, Result};
+use crate::format::{Compression, Format};
+use crate::wal::SyncPolicy;
+use crate::MiniDB;
+
+/// How to open a database, for when [`MiniDB::new`] and
+/// [`MiniDB::open_read_only`] are not enough:
+///
+/// ```ignore
+/// let db = OpenOptions::new()
+///     .create(false)
+///     .format(Format::Binary)
+///     .sync_policy(SyncPolicy::EveryMillis(100))
+///     .auto_save(Duration::from_secs(60))
+///     .open("./data")?;
+/// ```
+///
+/// By default the directory is created if needed and the database is loaded,
+/// so [`OpenOptions::open`] hands back a database that is ready to use.
+#[derive(Clone)]
+pub struct OpenOptions {
+    create: bool,
+    load: bool,
+    read_only: bool,
+    in_memory: bool,
+    format: Option<Format>,
+    compression: Compression,
+    sync_policy: SyncPolicy,
+    auto_save: Option<Duration>,
+    memory_limit: Option<usize>,
+    key: Option<EncryptionKey>,
+    old_keys: Vec<EncryptionKey>,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self {
+            create: true,
+            load: true,
+            read_only: false,
+            in_memory: false,
+            format: None,
+            compression: Compression::default(),
+            sync_policy: SyncPolicy::default(),
+            auto_save: None,
+            memory_limit: None,
+            key: None,
+            old_keys: Vec::new(),
+        }
+    }
+
+    /// Whether to create the directory if it does not exist. With `false`,
+    /// opening a missing database fails with an I/O error. On by default.
+    pub fn create(mut self, create: bool) -> Self {
+        self.create = create;
+        self
+    }
+
+    /// Whether to load the tables and replay the write-ahead log while
+    /// opening. On by default; a damaged table then makes the open fail. Turn
+    /// it off to call [`MiniDB::load`] yourself and decide what to do with
+    /// damaged tables.
+    pub fn load(mut self, load: bool) -> Self {
+        self.load = load;
+        self
+    }
+
+    /// Opens the database like [`MiniDB::open_read_only`]. The directory must
+    /// exist.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Keeps the database in memory only. It starts out with the tables
+    /// found in the directory, if it exists and loading is on, but never
+    /// writes to it, takes no lock and has no write-ahead log; saving only
+    /// marks the tables as clean.
+    pub fn in_memory(mut self, in_memory: bool) -> Self {
+        self.in_memory = in_memory;
+        self
+    }
+
+    /// See [`MiniDB::set_format`].
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// See [`MiniDB::set_compression`].
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// See [`MiniDB::set_sync_policy`].
+    pub fn sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.sync_policy = policy;
+        self
+    }
+
+    /// See [`MiniDB::set_auto_save`].
+    pub fn auto_save(mut self, interval: Duration) -> Self {
+        self.auto_save = Some(interval);
+        self
+    }
+
+    /// See [`MiniDB::set_memory_limit`]. Opening fails if the loaded data is
+    /// already over the limit.
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// See [`MiniDB::with_key`].
+    pub fn key(mut self, key: EncryptionKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// See [`MiniDB::with_old_key`].
+    pub fn old_key(mut self, key: EncryptionKey) -> Self {
+        self.old_keys.push(key);
+        self
+    }
+
+    /// Opens the database in directory `path` with these options.
+    pub fn open(self, path: &str) -> Result<MiniDB> {
+        let exists = match fs::metadata(path) {
+            Ok(_) => true,
+            Err(e) if e.kind() == ErrorKind::NotFound && self.create && !self.read_only => false,
+            Err(e) => return Err(OxidbError::io(path, e)),
+        };
+        let mut db = if self.in_memory {
+            // Read what is there without touching it, then let go of the directory.
+            let seed = if exists && self.load { MiniDB::open_read_only(path)? } else { MiniDB::in_memory() };
+            self.configure(seed)?.into_memory()
+        } else if self.read_only {
+            self.configure(MiniDB::open_read_only(path)?)?
+        } else {
+            self.configure(MiniDB::new(path)?)?
+        };
+        db.read_only = self.read_only;
+        db.set_auto_save(self.auto_save);
+        Ok(db)
+    }
+
+    /// Applies the options to a freshly opened database and loads it.
+    fn configure(&self, mut db: MiniDB) -> Result<MiniDB> {
+        if let Some(key) = &self.key {
+            db = db.with_key(key.clone());
+        }
+        for key in &self.old_keys {
+            db = db.with_old_key(key.clone());
+        }
+        if let Some(format) = self.format {
+            db.set_format(format);
+        }
+        db.set_compression(self.compression);
+        db.set_sync_policy(self.sync_policy);
+        db.set_memory_limit(self.memory_limit);
+        if self.load {
+            if let Some(problem) = db.load()?.into_iter().next() {
+                return Err(problem);
+            }
+            if let Some(limit) = self.memory_limit
+                && db.memory_usage() > limit
+            {
+                return Err(OxidbError::MemoryLimit { limit, needed: db.memory_usage() });
+            }
+        }
+        Ok(db)
+    }
+}
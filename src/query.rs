@@ -0,0 +1,401 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+use crate::error::{OxidbError, Result};
+use crate::index;
+use crate::table::{Record, Table};
+use crate::value::Value;
+
+/// A condition on the fields of a record, built with [`field`].
+///
+/// A field that is missing from a record counts as null. Null only matches
+/// `is_null`; every other comparison against it is false, like in SQL.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Eq(String, Value),
+    Ne(String, Value),
+    Lt(String, Value),
+    Le(String, Value),
+    Gt(String, Value),
+    Ge(String, Value),
+    In(String, Vec<Value>),
+    /// Substring of a string field, or element of a list field.
+    Contains(String, Value),
+    StartsWith(String, String),
+    IsNull(String),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+}
+
+/// Starts a filter on the named field: `field("age").gt(30)`.
+pub fn field(name: &str) -> Field {
+    Field(name.to_string())
+}
+
+/// Negates a filter.
+pub fn not(filter: Filter) -> Filter {
+    Filter::Not(Box::new(filter))
+}
+
+pub struct Field(String);
+
+impl Field {
+    pub fn eq(self, value: impl Into<Value>) -> Filter {
+        Filter::Eq(self.0, value.into())
+    }
+
+    pub fn ne(self, value: impl Into<Value>) -> Filter {
+        Filter::Ne(self.0, value.into())
+    }
+
+    pub fn lt(self, value: impl Into<Value>) -> Filter {
+        Filter::Lt(self.0, value.into())
+    }
+
+    pub fn le(self, value: impl Into<Value>) -> Filter {
+        Filter::Le(self.0, value.into())
+    }
+
+    pub fn gt(self, value: impl Into<Value>) -> Filter {
+        Filter::Gt(self.0, value.into())
+    }
+
+    pub fn ge(self, value: impl Into<Value>) -> Filter {
+        Filter::Ge(self.0, value.into())
+    }
+
+    pub fn is_in<V: Into<Value>>(self, values: impl IntoIterator<Item = V>) -> Filter {
+        Filter::In(self.0, values.into_iter().map(Into::into).collect())
+    }
+
+    pub fn contains(self, value: impl Into<Value>) -> Filter {
+        Filter::Contains(self.0, value.into())
+    }
+
+    pub fn starts_with(self, prefix: &str) -> Filter {
+        Filter::StartsWith(self.0, prefix.to_string())
+    }
+
+    pub fn is_null(self) -> Filter {
+        Filter::IsNull(self.0)
+    }
+
+    pub fn is_not_null(self) -> Filter {
+        not(Filter::IsNull(self.0))
+    }
+}
+
+impl Filter {
+    pub fn and(self, other: Filter) -> Filter {
+        Filter::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: Filter) -> Filter {
+        Filter::Or(Box::new(self), Box::new(other))
+    }
+
+    pub fn matches(&self, record: &Record) -> bool {
+        let get = |name: &str| record.data.get(name).filter(|v| !v.is_null());
+        let cmp = |name: &str, value: &Value| get(name).and_then(|v| v.compare(value));
+        match self {
+            Filter::Eq(f, v) => cmp(f, v) == Some(Ordering::Equal),
+            Filter::Ne(f, v) => get(f).is_some_and(|field| field.compare(v) != Some(Ordering::Equal)),
+            Filter::Lt(f, v) => cmp(f, v) == Some(Ordering::Less),
+            Filter::Le(f, v) => matches!(cmp(f, v), Some(Ordering::Less | Ordering::Equal)),
+            Filter::Gt(f, v) => cmp(f, v) == Some(Ordering::Greater),
+            Filter::Ge(f, v) => matches!(cmp(f, v), Some(Ordering::Greater | Ordering::Equal)),
+            Filter::In(f, values) => values.iter().any(|v| cmp(f, v) == Some(Ordering::Equal)),
+            Filter::Contains(f, v) => match (get(f), v) {
+                (Some(Value::String(s)), Value::String(needle)) => s.contains(needle.as_str()),
+                (Some(Value::List(items)), v) => items.iter().any(|item| item.compare(v) == Some(Ordering::Equal)),
+                _ => false,
+            },
+            Filter::StartsWith(f, prefix) => get(f).and_then(Value::as_str).is_some_and(|s| s.starts_with(prefix.as_str())),
+            Filter::IsNull(f) => get(f).is_none(),
+            Filter::And(a, b) => a.matches(record) && b.matches(record),
+            Filter::Or(a, b) => a.matches(record) || b.matches(record),
+            Filter::Not(a) => !a.matches(record),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nulls {
+    First,
+    Last,
+}
+
+/// One field to sort on, built with [`asc`] or [`desc`]. Unless told
+/// otherwise nulls sort as if they were larger than any value: last when
+/// ascending, first when descending.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub field: String,
+    pub direction: Direction,
+    pub nulls: Nulls,
+}
+
+pub fn asc(field: &str) -> SortKey {
+    SortKey { field: field.to_string(), direction: Direction::Asc, nulls: Nulls::Last }
+}
+
+pub fn desc(field: &str) -> SortKey {
+    SortKey { field: field.to_string(), direction: Direction::Desc, nulls: Nulls::First }
+}
+
+impl SortKey {
+    pub fn nulls_first(mut self) -> Self {
+        self.nulls = Nulls::First;
+        self
+    }
+
+    pub fn nulls_last(mut self) -> Self {
+        self.nulls = Nulls::Last;
+        self
+    }
+
+    fn compare(&self, a: Option<&Value>, b: Option<&Value>) -> Ordering {
+        match (a, b) {
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => if self.nulls == Nulls::First { Ordering::Less } else { Ordering::Greater },
+            (Some(_), None) => if self.nulls == Nulls::First { Ordering::Greater } else { Ordering::Less },
+            (Some(a), Some(b)) => match self.direction {
+                Direction::Asc => a.total_cmp(b),
+                Direction::Desc => b.total_cmp(a),
+            },
+        }
+    }
+}
+
+/// An opaque position in an ordered result, handed out by [`Query::page`].
+/// Passing it to [`Query::after`] continues right behind the last record of
+/// that page, no matter what was inserted or deleted in the meantime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor(String);
+
+impl Cursor {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn encode(keys: &[SortKey], record: &Record) -> Cursor {
+        let position = CursorPosition {
+            keys: keys.iter().map(|k| k.field.clone()).collect(),
+            values: keys.iter().map(|k| sort_value(record, &k.field).cloned().unwrap_or(Value::Null)).collect(),
+            id: record.id,
+        };
+        let json = serde_json::to_vec(&position).expect("cursor positions always serialize");
+        Cursor(json.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    fn decode(&self, keys: &[SortKey]) -> Result<CursorPosition> {
+        let invalid = |reason: &str| OxidbError::InvalidCursor { reason: reason.to_string() };
+        let bytes = (0..self.0.len())
+            .step_by(2)
+            .map(|i| self.0.get(i..i + 2).and_then(|h| u8::from_str_radix(h, 16).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| invalid("not a cursor"))?;
+        let position: CursorPosition = serde_json::from_slice(&bytes).map_err(|_| invalid("not a cursor"))?;
+        if position.values.len() != position.keys.len() || !position.keys.iter().eq(keys.iter().map(|k| &k.field)) {
+            return Err(invalid("cursor was made for a different ordering"));
+        }
+        Ok(position)
+    }
+}
+
+impl From<String> for Cursor {
+    fn from(s: String) -> Self {
+        Cursor(s)
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CursorPosition {
+    keys: Vec<String>,
+    values: Vec<Value>,
+    id: u64,
+}
+
+/// One page of query results.
+pub struct Page<'a> {
+    pub records: Vec<&'a Record>,
+    /// Where the next page starts, or `None` if this was the last page.
+    pub next: Option<Cursor>,
+}
+
+fn sort_value<'r>(record: &'r Record, field: &str) -> Option<&'r Value> {
+    record.data.get(field).filter(|v| !v.is_null())
+}
+
+/// A query over one table, created with [`MiniDB::query`] or
+/// [`Snapshot::query`](crate::Snapshot::query). Nothing is read
+/// until one of `records`, `count`, `ids` or `page` is called.
+///
+/// Results come back in the order given with `order_by`, ties (and queries
+/// without an ordering) broken by ascending record id, so the same query
+/// always returns records in the same order.
+pub struct Query<'a> {
+    tables: &'a HashMap<String, Arc<Table>>,
+    table: String,
+    filter: Option<Filter>,
+    order: Vec<SortKey>,
+    after: Option<Cursor>,
+    offset: usize,
+    limit: Option<usize>,
+}
+
+impl<'a> Query<'a> {
+    pub(crate) fn new(tables: &'a HashMap<String, Arc<Table>>, table: &str) -> Self {
+        Self {
+            tables,
+            table: table.to_string(),
+            filter: None,
+            order: Vec::new(),
+            after: None,
+            offset: 0,
+            limit: None,
+        }
+    }
+
+    pub(crate) fn table_name(&self) -> &str {
+        &self.table
+    }
+
+    /// Adds a condition; several calls are combined with AND.
+    pub fn filter(self, filter: Filter) -> Self {
+        self.and(filter)
+    }
+
+    pub fn and(mut self, filter: Filter) -> Self {
+        self.filter = Some(match self.filter.take() {
+            Some(existing) => existing.and(filter),
+            None => filter,
+        });
+        self
+    }
+
+    /// Widens the query: records matching the conditions so far, or `filter`.
+    pub fn or(mut self, filter: Filter) -> Self {
+        self.filter = Some(match self.filter.take() {
+            Some(existing) => existing.or(filter),
+            None => filter,
+        });
+        self
+    }
+
+    /// Sorts on another field; earlier keys take precedence.
+    pub fn order_by(mut self, key: SortKey) -> Self {
+        self.order.push(key);
+        self
+    }
+
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Only returns records that come after `cursor` in this query's ordering.
+    pub fn after(mut self, cursor: Cursor) -> Self {
+        self.after = Some(cursor);
+        self
+    }
+
+    /// The matching records, borrowed from the database.
+    pub fn records(&self) -> Result<Vec<&'a Record>> {
+        self.run(self.limit)
+    }
+
+    /// The number of records `records` would return.
+    pub fn count(&self) -> Result<usize> {
+        if self.after.is_none() && self.offset == 0 && self.limit.is_none() {
+            return Ok(self.matching()?.count());
+        }
+        Ok(self.records()?.len())
+    }
+
+    pub fn ids(&self) -> Result<Vec<u64>> {
+        Ok(self.records()?.into_iter().map(|r| r.id).collect())
+    }
+
+    /// Returns up to `size` records, plus a cursor for the next page if there
+    /// are more. A `size` of 0 is refused, since a page without records has
+    /// no position to continue from.
+    pub fn page(&self, size: usize) -> Result<Page<'a>> {
+        if size == 0 {
+            return Err(OxidbError::InvalidPageSize { size });
+        }
+        let mut records = self.run(Some(size.saturating_add(1)))?;
+        let next = if records.len() > size {
+            records.truncate(size);
+            records.last().map(|r| Cursor::encode(&self.order, r))
+        } else {
+            None
+        };
+        Ok(Page { records, next })
+    }
+
+    fn run(&self, limit: Option<usize>) -> Result<Vec<&'a Record>> {
+        let after = self.after.as_ref().map(|c| c.decode(&self.order)).transpose()?;
+        let mut records: Vec<&'a Record> = self.matching()?.collect();
+        records.sort_by(|a, b| self.compare(a, b));
+        if let Some(position) = after {
+            let start = records.partition_point(|r| self.compare_to_position(r, &position) != Ordering::Greater);
+            records.drain(..start);
+        }
+        let end = limit.map_or(records.len(), |l| self.offset.saturating_add(l).min(records.len()));
+        Ok(records.drain(self.offset.min(end)..end).collect())
+    }
+
+    fn compare(&self, a: &Record, b: &Record) -> Ordering {
+        self.order.iter()
+            .map(|k| k.compare(sort_value(a, &k.field), sort_value(b, &k.field)))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| a.id.cmp(&b.id))
+    }
+
+    fn compare_to_position(&self, record: &Record, position: &CursorPosition) -> Ordering {
+        self.order.iter()
+            .zip(&position.values)
+            .map(|(k, v)| k.compare(sort_value(record, &k.field), Some(v).filter(|v| !v.is_null())))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| record.id.cmp(&position.id))
+    }
+
+    fn matching(&self) -> Result<Box<dyn Iterator<Item = &'a Record> + '_>> {
+        let table = table(self.tables, &self.table)?;
+        let Some(filter) = &self.filter else {
+            return Ok(Box::new(table.records.values()));
+        };
+        match index::plan(&table.index_data, filter) {
+            Some(ids) => Ok(Box::new(
+                ids.into_iter().filter_map(|id| table.records.get(&id)).filter(move |r| filter.matches(r)),
+            )),
+            None => Ok(Box::new(table.records.values().filter(move |r| filter.matches(r)))),
+        }
+    }
+}
+
+/// Looks up a table by name, for the databases and snapshots that queries run on.
+pub(crate) fn table<'a>(tables: &'a HashMap<String, Arc<Table>>, name: &str) -> Result<&'a Table> {
+    tables.get(name).map(Arc::as_ref).ok_or_else(|| OxidbError::TableNotFound { table: name.to_string() })
+}
@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+use serde::{Serialize, Deserialize};
+use crate::error::{OxidbError, Result};
+use crate::table::Record;
+use crate::value::Value;
+
+/// The type a column accepts. `Any` switches type checking off for a column
+/// while still enforcing its nullability and default.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnType {
+    Any,
+    Bool,
+    Int,
+    /// Floats; ints are accepted and stored as floats.
+    Float,
+    String,
+    Bytes,
+    Timestamp,
+    List,
+    Map,
+}
+
+impl ColumnType {
+    fn accepts(self, value: &Value) -> bool {
+        matches!(
+            (self, value),
+            (ColumnType::Any, _)
+                | (ColumnType::Bool, Value::Bool(_))
+                | (ColumnType::Int, Value::Int(_))
+                | (ColumnType::Float, Value::Float(_) | Value::Int(_))
+                | (ColumnType::String, Value::String(_))
+                | (ColumnType::Bytes, Value::Bytes(_))
+                | (ColumnType::Timestamp, Value::Timestamp(_))
+                | (ColumnType::List, Value::List(_))
+                | (ColumnType::Map, Value::Map(_))
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Column {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: ColumnType,
+    #[serde(default)]
+    pub nullable: bool,
+    /// Filled in when a record is inserted without this field.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<Value>,
+}
+
+impl Column {
+    /// A NOT NULL column without a default.
+    pub fn new(name: &str, ty: ColumnType) -> Self {
+        Self { name: name.to_string(), ty, nullable: false, default: None }
+    }
+
+    pub fn nullable(mut self) -> Self {
+        self.nullable = true;
+        self
+    }
+
+    pub fn default(mut self, value: impl Into<Value>) -> Self {
+        self.default = Some(value.into());
+        self
+    }
+}
+
+/// Optional column definitions for a table. Tables without a schema accept
+/// any record.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct Schema {
+    pub columns: Vec<Column>,
+    /// Whether records may carry fields that are not declared as a column.
+    #[serde(default)]
+    pub allow_extra_fields: bool,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn column(mut self, column: Column) -> Self {
+        self.columns.push(column);
+        self
+    }
+
+    pub fn allow_extra_fields(mut self, allow: bool) -> Self {
+        self.allow_extra_fields = allow;
+        self
+    }
+
+    pub fn get_column(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|c| c.name == name)
+    }
+
+    /// Checks the schema itself: column names must be unique and defaults
+    /// must satisfy their own column.
+    pub(crate) fn validate(&self, table: &str) -> Result<()> {
+        let mut seen = HashSet::new();
+        for column in &self.columns {
+            if !seen.insert(column.name.as_str()) {
+                return Err(violation(table, &column.name, "column is declared twice".to_string()));
+            }
+            if let Some(default) = &column.default {
+                check_value(table, column, default)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills in defaults and checks the record against the schema.
+    pub(crate) fn conform(&self, table: &str, record: &mut Record) -> Result<()> {
+        for column in &self.columns {
+            match record.data.get_mut(&column.name) {
+                Some(value) => {
+                    check_value(table, column, value)?;
+                    coerce(column, value);
+                }
+                None => match &column.default {
+                    Some(default) => {
+                        let mut value = default.clone();
+                        coerce(column, &mut value);
+                        record.data.insert(column.name.clone(), value);
+                    }
+                    None if column.nullable => {}
+                    None => return Err(violation(table, &column.name, "required field is missing".to_string())),
+                },
+            }
+        }
+        if !self.allow_extra_fields
+            && let Some(extra) = record.data.keys().find(|k| self.get_column(k).is_none())
+        {
+            return Err(violation(table, extra, "field is not declared in the schema".to_string()));
+        }
+        Ok(())
+    }
+}
+
+fn check_value(table: &str, column: &Column, value: &Value) -> Result<()> {
+    if value.is_null() {
+        if column.nullable {
+            return Ok(());
+        }
+        return Err(violation(table, &column.name, "column is NOT NULL".to_string()));
+    }
+    if !column.ty.accepts(value) {
+        return Err(violation(
+            table,
+            &column.name,
+            format!("expected {:?}, got {}", column.ty, value.type_name()),
+        ));
+    }
+    Ok(())
+}
+
+/// Stores an int in a float column as a float, so the column holds one type.
+fn coerce(column: &Column, value: &mut Value) {
+    if let (ColumnType::Float, Value::Int(i)) = (column.ty, &*value) {
+        *value = Value::Float(*i as f64);
+    }
+}
+
+fn violation(table: &str, field: &str, reason: String) -> OxidbError {
+    OxidbError::SchemaViolation { table: table.to_string(), field: field.to_string(), reason }
+}
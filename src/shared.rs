@@ -0,0 +1,136 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use crate::error::{OxidbError, Result};
+use crate::snapshot::Snapshot;
+use crate::table::Record;
+use crate::MiniDB;
+
+/// A cloneable, thread-safe handle to a [`MiniDB`].
+///
+/// Any number of threads can read at the same time. Writes are applied one at
+/// a time and only hold up readers for as long as the change itself takes.
+/// [`SharedDb::save`] writes the table files while holding off other writers
+/// but not readers, so reads never wait for a save to finish. The same goes
+/// for the automatic checkpoints of [`MiniDB::set_auto_save`], which run
+/// after the write that made them due.
+#[derive(Clone)]
+pub struct SharedDb {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    // Held by whoever is changing the database, including a save, which
+    // changes the files. Writers take it before the write lock, so a save
+    // holding it and a read lock never has a writer queued on `db` that
+    // would stall new readers.
+    writer: Mutex<()>,
+    db: RwLock<MiniDB>,
+}
+
+impl SharedDb {
+    pub fn new(mut db: MiniDB) -> Self {
+        db.shared = true;
+        Self { inner: Arc::new(Inner { writer: Mutex::new(()), db: RwLock::new(db) }) }
+    }
+
+    /// Runs `f` with shared access to the database:
+    /// `shared.read(|db| db.query("users").count())`.
+    pub fn read<T>(&self, f: impl FnOnce(&MiniDB) -> T) -> T {
+        f(&self.read_lock())
+    }
+
+    /// Runs `f` with exclusive access to the database. Readers wait while it
+    /// runs, so keep it short.
+    ///
+    /// A transaction cannot span calls: one that `f` leaves open is rolled
+    /// back, and the call fails with [`OxidbError::TransactionActive`]. Use
+    /// [`SharedDb::transaction`] instead.
+    pub fn write<T>(&self, f: impl FnOnce(&mut MiniDB) -> Result<T>) -> Result<T> {
+        let _writer = self.writer_lock();
+        let mut db = self.write_lock();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&mut db)));
+        // Left open, its changes would be visible to readers without ever
+        // being logged, and every later save would fail.
+        let left_open = db.tx.is_some() && db.rollback().is_ok();
+        let result = match result {
+            Err(payload) => panic::resume_unwind(payload),
+            Ok(_) if left_open => Err(OxidbError::TransactionActive),
+            Ok(result) => result,
+        };
+        // Automatic checkpoints go the way of `save`, so readers do not wait
+        // for them either.
+        if db.auto_save_due() {
+            drop(db);
+            if let Err(e) = self.checkpoint() {
+                self.write_lock().auto_save_error = Some(e);
+            }
+        }
+        result
+    }
+
+    /// Runs `f` as a transaction, see [`MiniDB::transaction`]. Readers wait
+    /// until it commits or rolls back, so they never see its changes halfway.
+    pub fn transaction<T>(&self, f: impl FnOnce(&mut MiniDB) -> Result<T>) -> Result<T> {
+        self.write(|db| db.transaction(f))
+    }
+
+    /// Returns a copy of a record.
+    pub fn get(&self, table: &str, id: u64) -> Result<Option<Record>> {
+        self.read(|db| db.get(table, id).map(|r| r.cloned()))
+    }
+
+    /// A consistent view of all tables as they are now, see
+    /// [`MiniDB::snapshot`]. Only taking it needs the read lock; reading from
+    /// it afterwards takes no locks at all.
+    pub fn snapshot(&self) -> Snapshot {
+        self.read(MiniDB::snapshot)
+    }
+
+    /// Checkpoints the database, see [`MiniDB::checkpoint`]. The table files
+    /// are written under a read lock; only the final bookkeeping takes the
+    /// write lock.
+    pub fn save(&self) -> Result<()> {
+        let _writer = self.writer_lock();
+        self.checkpoint()
+    }
+
+    /// Unwraps the database if this is the last handle to it.
+    pub fn into_inner(self) -> std::result::Result<MiniDB, SharedDb> {
+        match Arc::try_unwrap(self.inner) {
+            Ok(inner) => {
+                let mut db = inner.db.into_inner().unwrap_or_else(PoisonError::into_inner);
+                db.shared = false;
+                Ok(db)
+            }
+            Err(inner) => Err(SharedDb { inner }),
+        }
+    }
+
+    // Callers hold the writer lock.
+    fn checkpoint(&self) -> Result<()> {
+        self.read_lock().write_changes()?;
+        self.write_lock().finish_checkpoint()
+    }
+
+    // Every change is validated before it is applied and a transaction rolls
+    // back when its closure panics, so a panic leaves only whole, logged
+    // changes behind; poisoned locks are taken over rather than passed on as
+    // a panic.
+    fn writer_lock(&self) -> MutexGuard<'_, ()> {
+        self.inner.writer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn read_lock(&self) -> RwLockReadGuard<'_, MiniDB> {
+        self.inner.db.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_lock(&self) -> RwLockWriteGuard<'_, MiniDB> {
+        self.inner.db.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl From<MiniDB> for SharedDb {
+    fn from(db: MiniDB) -> Self {
+        SharedDb::new(db)
+    }
+}
@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::error::Result;
+use crate::index::IndexDef;
+use crate::query::{self, Query};
+use crate::table::{Record, Table};
+
+/// A read-only view of all tables as of the moment it was taken with
+/// [`MiniDB::snapshot`](crate::MiniDB::snapshot) or
+/// [`SharedDb::snapshot`](crate::SharedDb::snapshot).
+///
+/// Reads against a snapshot never see later changes and never wait for
+/// writers, so a long report can iterate over it while the database keeps
+/// changing. Cloning a snapshot is cheap.
+#[derive(Clone)]
+pub struct Snapshot {
+    tables: HashMap<String, Arc<Table>>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(tables: HashMap<String, Arc<Table>>) -> Self {
+        Self { tables }
+    }
+
+    pub fn table_exists(&self, name: &str) -> bool {
+        self.tables.contains_key(name)
+    }
+
+    /// Names of all tables, sorted alphabetically.
+    pub fn list_tables(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.tables.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn get(&self, table: &str, id: u64) -> Result<Option<&Record>> {
+        Ok(query::table(&self.tables, table)?.records.get(&id))
+    }
+
+    /// Starts a query over the records of `table`, as for [`MiniDB::query`](crate::MiniDB::query).
+    pub fn query(&self, table: &str) -> Query<'_> {
+        Query::new(&self.tables, table)
+    }
+
+    /// The indexes defined on `table`.
+    pub fn indexes(&self, table: &str) -> Result<&[IndexDef]> {
+        Ok(&query::table(&self.tables, table)?.indexes)
+    }
+}
@@ -0,0 +1,121 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use crate::crypto::Keyring;
+use crate::error::{OxidbError, Result};
+use crate::format::{self, Compression, Format};
+use crate::table::Table;
+
+/// Suffix of the temporary file a table is written to before it is renamed
+/// over the real one.
+const TEMP_SUFFIX: &str = ".tmp";
+
+/// Name of the lock file inside the database directory.
+pub(crate) const LOCK_FILE: &str = "oxidb.lock";
+
+/// Takes the exclusive advisory lock on the database directory `dir`. The lock
+/// is held for as long as the returned file stays open, and the operating
+/// system drops it if the process dies.
+pub(crate) fn lock_dir(dir: &Path) -> Result<File> {
+    let path = dir.join(LOCK_FILE);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .map_err(|e| OxidbError::io(&path, e))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(OxidbError::Locked { path: dir.to_path_buf() }),
+        Err(TryLockError::Error(e)) => Err(OxidbError::io(&path, e)),
+    }
+}
+
+/// Writes `bytes` to `path` so that `path` afterwards holds either its old
+/// contents or all of `bytes`, even if the process or machine dies halfway:
+/// write to a temporary file, fsync it, rename it over `path`, then fsync the
+/// directory so the rename itself is durable.
+pub(crate) fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    let tmp = temp_path(path);
+    let result = (|| {
+        let mut file = File::create(&tmp).map_err(|e| OxidbError::io(&tmp, e))?;
+        file.write_all(bytes).map_err(|e| OxidbError::io(&tmp, e))?;
+        file.sync_all().map_err(|e| OxidbError::io(&tmp, e))?;
+        fs::rename(&tmp, path).map_err(|e| OxidbError::io(path, e))
+    })();
+    if result.is_err() {
+        // Best effort: a temp file left behind is also removed by the next load.
+        let _ = fs::remove_file(&tmp);
+    }
+    result?;
+    sync_parent(path)
+}
+
+/// Deletes `path` if it exists and makes the deletion durable.
+pub(crate) fn remove_durably(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => sync_parent(path),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(OxidbError::io(path, e)),
+    }
+}
+
+pub(crate) fn write_table(
+    path: &Path,
+    format: Format,
+    compression: Compression,
+    keys: &Keyring,
+    table: &Table,
+) -> Result<()> {
+    write_atomic(path, &format::encode_table(format, compression, keys, path, table)?)
+}
+
+/// Reads and parses a single table file, in either format. Also returns
+/// whether the file should be rewritten to be encrypted with the current key.
+pub(crate) fn read_table(path: &Path, keys: &Keyring) -> Result<(Table, bool)> {
+    let data = fs::read(path).map_err(|e| OxidbError::io(path, e))?;
+    let (mut table, stale) = format::decode_table(path, &data, keys)?;
+    // The name decides where the table is saved, so it must be the one the
+    // file was found under.
+    let corrupt = |reason: String| OxidbError::CorruptTable { path: path.to_path_buf(), table: Some(table.name.clone()), reason };
+    if let Err(e) = crate::validate_table_name(&table.name) {
+        return Err(corrupt(e.to_string()));
+    }
+    if path.file_stem() != Some(table.name.as_ref()) {
+        return Err(corrupt("table name does not match the file name".to_string()));
+    }
+    table.restore_sequence();
+    table.rebuild_indexes().map_err(|(fields, a, b)| OxidbError::CorruptTable {
+        path: path.to_path_buf(),
+        table: Some(table.name.clone()),
+        reason: format!("records {} and {} break the unique constraint on ({})", a, b, fields.join(", ")),
+    })?;
+    Ok((table, stale))
+}
+
+/// Whether `path` is a temporary file left behind by an interrupted write.
+pub(crate) fn is_temp_file(path: &Path) -> bool {
+    path.file_name().is_some_and(|n| n.to_string_lossy().ends_with(TEMP_SUFFIX))
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(TEMP_SUFFIX);
+    path.with_file_name(name)
+}
+
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir).and_then(|d| d.sync_all()).map_err(|e| OxidbError::io(dir, e))
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<()> {
+    // Directories cannot be opened for syncing here; the rename is as
+    // durable as the platform makes it.
+    Ok(())
+}
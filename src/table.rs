@@ -0,0 +1,310 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use crate::error::{OxidbError, Result};
+use crate::format::Compression;
+use crate::index::{Index, IndexDef, UniqueConstraint, UniqueIndex};
+use crate::schema::Schema;
+use crate::value::Value;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Record {
+    pub id: u64,
+    pub data: HashMap<String, Value>,
+}
+
+impl Record {
+    pub fn new(id: u64) -> Self {
+        Self { id, data: HashMap::new() }
+    }
+
+    /// Builder-style setter: `Record::new(1).with("name", "Stan")`.
+    pub fn with(mut self, field: &str, value: impl Into<Value>) -> Self {
+        self.data.insert(field.to_string(), value.into());
+        self
+    }
+
+    pub fn get(&self, field: &str) -> Option<&Value> {
+        self.data.get(field)
+    }
+
+    /// Roughly how many bytes the record takes up in memory.
+    pub(crate) fn approx_size(&self) -> usize {
+        std::mem::size_of::<Record>() + self.data.iter().map(|(k, v)| k.len() + v.approx_size()).sum::<usize>()
+    }
+
+    pub fn get_bool(&self, field: &str) -> Result<bool> {
+        self.typed(field, "bool", Value::as_bool)
+    }
+
+    pub fn get_i64(&self, field: &str) -> Result<i64> {
+        self.typed(field, "int", Value::as_i64)
+    }
+
+    /// Reads a float; int fields are widened.
+    pub fn get_f64(&self, field: &str) -> Result<f64> {
+        self.typed(field, "float", Value::as_f64)
+    }
+
+    pub fn get_str(&self, field: &str) -> Result<&str> {
+        self.typed(field, "string", Value::as_str)
+    }
+
+    pub fn get_bytes(&self, field: &str) -> Result<&[u8]> {
+        self.typed(field, "bytes", Value::as_bytes)
+    }
+
+    pub fn get_timestamp(&self, field: &str) -> Result<i64> {
+        self.typed(field, "timestamp", Value::as_timestamp)
+    }
+
+    pub fn get_list(&self, field: &str) -> Result<&[Value]> {
+        self.typed(field, "list", Value::as_list)
+    }
+
+    pub fn get_map(&self, field: &str) -> Result<&BTreeMap<String, Value>> {
+        self.typed(field, "map", Value::as_map)
+    }
+
+    fn typed<'a, T>(&'a self, field: &str, expected: &'static str, get: impl Fn(&'a Value) -> Option<T>) -> Result<T> {
+        let value = self.data.get(field).ok_or_else(|| OxidbError::FieldNotFound { field: field.to_string() })?;
+        get(value).ok_or_else(|| OxidbError::TypeMismatch {
+            field: field.to_string(),
+            expected,
+            found: value.type_name(),
+        })
+    }
+}
+
+/// How a table hands out ids for records inserted with `MiniDB::insert_new`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IdStrategy {
+    /// 1, 2, 3, ... from a per-table sequence that is persisted with the table.
+    #[default]
+    AutoIncrement,
+    /// Random 64-bit ids. They are spread out, not secret: do not rely on
+    /// them being hard to guess.
+    Random,
+    /// Ids that sort by creation time: the upper 48 bits are a millisecond
+    /// timestamp, the lower 16 bits are random. Ids issued by one table are
+    /// strictly increasing, even within the same millisecond.
+    TimeOrdered,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Table {
+    pub name: String,
+    pub records: HashMap<u64, Record>,
+    #[serde(default)]
+    pub id_strategy: IdStrategy,
+    /// Lowest id the table's sequence has not handed out or seen yet. Ids
+    /// below this value are never assigned again, even after a delete.
+    #[serde(default)]
+    pub next_id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Schema>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub indexes: Vec<IndexDef>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unique: Vec<UniqueConstraint>,
+    /// Compression for this table's file, overriding the database's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    /// Contents of the indexes in `indexes`, kept in sync by `put`, `remove`
+    /// and `clear`.
+    #[serde(skip)]
+    pub(crate) index_data: Vec<Index>,
+    /// Contents of the indexes behind the constraints in `unique`.
+    #[serde(skip)]
+    pub(crate) unique_data: Vec<UniqueIndex>,
+    /// Sum of the sizes of the records, kept up to date like the indexes.
+    #[serde(skip)]
+    pub(crate) data_size: usize,
+}
+
+impl Table {
+    pub fn new(name: &str, id_strategy: IdStrategy) -> Self {
+        Self {
+            name: name.to_string(),
+            records: HashMap::new(),
+            id_strategy,
+            next_id: 1,
+            schema: None,
+            indexes: Vec::new(),
+            unique: Vec::new(),
+            compression: None,
+            index_data: Vec::new(),
+            unique_data: Vec::new(),
+            data_size: 0,
+        }
+    }
+
+    /// Fails if storing `record` would break one of the unique constraints.
+    pub(crate) fn check_unique(&self, record: &Record) -> Result<()> {
+        for index in &self.unique_data {
+            if let Some(conflicting_id) = index.conflict(record) {
+                return Err(OxidbError::ConstraintViolation {
+                    table: self.name.clone(),
+                    fields: index.def.fields.clone(),
+                    conflicting_id,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores a record, replacing the one with the same id, and keeps the
+    /// indexes up to date. All writes to `records` go through here or through
+    /// `remove` and `clear`.
+    pub(crate) fn put(&mut self, record: Record) -> Option<Record> {
+        let old = self.records.remove(&record.id);
+        self.data_size = self.data_size + record.approx_size() - old.as_ref().map_or(0, Record::approx_size);
+        for index in &mut self.index_data {
+            if let Some(old) = &old {
+                index.remove(old);
+            }
+            index.insert(&record);
+        }
+        for index in &mut self.unique_data {
+            if let Some(old) = &old {
+                index.remove(old);
+            }
+            index.insert(&record);
+        }
+        self.records.insert(record.id, record);
+        old
+    }
+
+    pub(crate) fn remove(&mut self, id: u64) -> Option<Record> {
+        let old = self.records.remove(&id)?;
+        self.data_size -= old.approx_size();
+        self.index_data.iter_mut().for_each(|index| index.remove(&old));
+        self.unique_data.iter_mut().for_each(|index| index.remove(&old));
+        Some(old)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.records.clear();
+        self.data_size = 0;
+        self.index_data.iter_mut().for_each(Index::clear);
+        self.unique_data.iter_mut().for_each(UniqueIndex::clear);
+    }
+
+    pub(crate) fn add_index(&mut self, def: IndexDef) {
+        self.index_data.push(Index::build(def.clone(), self.records.values()));
+        self.indexes.push(def);
+    }
+
+    pub(crate) fn remove_index(&mut self, field: &str) -> bool {
+        let before = self.indexes.len();
+        self.indexes.retain(|d| d.field != field);
+        self.index_data.retain(|i| i.def.field != field);
+        self.indexes.len() != before
+    }
+
+    /// Fails if the current records already break the constraint.
+    pub(crate) fn check_new_unique(&self, def: &UniqueConstraint) -> Result<()> {
+        self.build_unique(def).map(|_| ())
+    }
+
+    /// Adds a unique constraint, failing if the current records already
+    /// break it.
+    pub(crate) fn add_unique(&mut self, def: UniqueConstraint) -> Result<()> {
+        let index = self.build_unique(&def)?;
+        self.unique_data.push(index);
+        self.unique.push(def);
+        Ok(())
+    }
+
+    fn build_unique(&self, def: &UniqueConstraint) -> Result<UniqueIndex> {
+        UniqueIndex::build(def.clone(), self.sorted_records()).map_err(|(conflicting_id, _)| {
+            OxidbError::ConstraintViolation { table: self.name.clone(), fields: def.fields.clone(), conflicting_id }
+        })
+    }
+
+    pub(crate) fn remove_unique(&mut self, fields: &[String]) -> bool {
+        let before = self.unique.len();
+        self.unique.retain(|c| c.fields != fields);
+        self.unique_data.retain(|i| i.def.fields != fields);
+        self.unique.len() != before
+    }
+
+    /// Builds the index contents from the index definitions, and works out
+    /// the size of the records, after loading.
+    /// A file that breaks one of its own unique constraints (for example
+    /// after a manual edit) is reported as corrupt by the caller.
+    pub(crate) fn rebuild_indexes(&mut self) -> std::result::Result<(), (Vec<String>, u64, u64)> {
+        self.data_size = self.records.values().map(Record::approx_size).sum();
+        self.index_data = self.indexes.iter()
+            .map(|def| Index::build(def.clone(), self.records.values()))
+            .collect();
+        self.unique_data = self.unique.iter()
+            .map(|def| {
+                UniqueIndex::build(def.clone(), self.sorted_records()).map_err(|(a, b)| (def.fields.clone(), a, b))
+            })
+            .collect::<std::result::Result<_, _>>()?;
+        Ok(())
+    }
+
+    fn sorted_records(&self) -> impl Iterator<Item = &Record> {
+        let mut records: Vec<&Record> = self.records.values().collect();
+        records.sort_by_key(|r| r.id);
+        records.into_iter()
+    }
+
+    /// Applies the table's schema, if it has one, to a record that is about
+    /// to be stored.
+    pub(crate) fn conform(&self, record: &mut Record) -> Result<()> {
+        match &self.schema {
+            Some(schema) => schema.conform(&self.name, record),
+            None => Ok(()),
+        }
+    }
+
+    /// Picks an id for a new record according to the table's id strategy.
+    /// The sequence only advances once a record with that id is stored.
+    pub(crate) fn new_record_id(&self) -> u64 {
+        match self.id_strategy {
+            IdStrategy::AutoIncrement => self.next_id.max(1),
+            IdStrategy::Random => loop {
+                let id = random_u64();
+                if id != 0 && !self.records.contains_key(&id) {
+                    break id;
+                }
+            },
+            IdStrategy::TimeOrdered => {
+                let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+                let candidate = (millis << 16) | (random_u64() & 0xFFFF);
+                candidate.max(self.next_id.max(1))
+            }
+        }
+    }
+
+    /// Advances the sequence past `id`, so it is never handed out later.
+    pub(crate) fn observe_id(&mut self, id: u64) {
+        if id >= self.next_id && self.id_strategy != IdStrategy::Random {
+            self.next_id = id.saturating_add(1);
+        }
+    }
+
+    /// Brings the sequence up to date after the table was read from a file
+    /// written by an older version that did not store it.
+    pub(crate) fn restore_sequence(&mut self) {
+        if let Some(max) = self.records.keys().max().copied() {
+            self.observe_id(max);
+        }
+        self.next_id = self.next_id.max(1);
+    }
+}
+
+fn random_u64() -> u64 {
+    // std seeds the keys of `RandomState` randomly once per thread and then
+    // only increments them, so mixing in the time is what keeps these apart.
+    // Good enough to spread ids out and avoid a random number crate, but
+    // not unpredictable.
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos());
+    hasher.finish()
+}
@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use serde::Serialize;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess};
+use crate::error::{OxidbError, Result};
+use crate::index::{IndexDef, UniqueConstraint};
+use crate::query::{Cursor, Filter, Query, SortKey};
+use crate::schema::{ColumnType, Schema};
+use crate::table::{IdStrategy, Record, Table};
+use crate::value::{Value, ValueSerializer};
+use crate::MiniDB;
+
+/// A Rust type stored as the records of a table, for use with
+/// [`MiniDB::table`] and [`MiniDB::open_table`].
+///
+/// The type is converted through serde: its fields become the fields of the
+/// record, except the id field, which is stored as the record id. Typed and
+/// untyped access therefore see the same data.
+///
+/// Usually derived, together with serde's traits:
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize, OxidbRecord)]
+/// #[oxidb(table = "users")]
+/// struct User {
+///     id: u64,
+///     #[oxidb(unique)]
+///     email: String,
+///     #[oxidb(index)]
+///     age: Option<i64>,
+/// }
+/// ```
+///
+/// The derive also declares the table's schema, indexes and unique
+/// constraints; see [`OxidbRecord`](derive@crate::OxidbRecord) for the
+/// attributes.
+pub trait OxidbRecord: Serialize + DeserializeOwned {
+    /// Name of the field that holds the id. It is not stored with the other
+    /// fields, and is filled in from the record id when reading.
+    const ID_FIELD: &'static str = "id";
+    /// The table [`MiniDB::open_table`] stores the type in.
+    const TABLE: &'static str;
+    const ID_STRATEGY: IdStrategy = IdStrategy::AutoIncrement;
+
+    fn id(&self) -> u64;
+
+    /// The schema of the table, or `None` to accept any record.
+    fn schema() -> Option<Schema> {
+        None
+    }
+
+    fn indexes() -> Vec<IndexDef> {
+        Vec::new()
+    }
+
+    fn unique() -> Vec<UniqueConstraint> {
+        Vec::new()
+    }
+}
+
+/// The empty table `T` declares, as [`MiniDB::open_table`] creates it.
+pub(crate) fn declared_table<T: OxidbRecord>() -> Result<Table> {
+    crate::validate_table_name(T::TABLE)?;
+    let mut table = Table::new(T::TABLE, T::ID_STRATEGY);
+    if let Some(schema) = T::schema() {
+        schema.validate(T::TABLE)?;
+        table.schema = Some(schema);
+    }
+    table.indexes = T::indexes();
+    table.unique = T::unique();
+    Ok(table)
+}
+
+/// Fails with [`OxidbError::SchemaMismatch`] if the stored `table` differs
+/// from what `T` declares.
+pub(crate) fn check_declared<T: OxidbRecord>(table: &Table) -> Result<()> {
+    let mismatch = |reason: String| Err(OxidbError::SchemaMismatch { table: table.name.clone(), reason });
+    if table.id_strategy != T::ID_STRATEGY {
+        return mismatch(format!("id strategy is {:?}, declared {:?}", table.id_strategy, T::ID_STRATEGY));
+    }
+    match (&table.schema, T::schema()) {
+        (None, None) => {}
+        (Some(_), None) => return mismatch("table has a schema, the type declares none".to_string()),
+        (None, Some(_)) => return mismatch("type declares a schema, the table has none".to_string()),
+        (Some(stored), Some(declared)) => {
+            for column in &declared.columns {
+                match stored.get_column(&column.name) {
+                    None => return mismatch(format!("column '{}' is not in the table", column.name)),
+                    Some(c) if c != column => {
+                        return mismatch(format!("column '{}' is {:?} in the table, declared {:?}", column.name, c, column));
+                    }
+                    Some(_) => {}
+                }
+            }
+            if let Some(c) = stored.columns.iter().find(|c| declared.get_column(&c.name).is_none()) {
+                return mismatch(format!("column '{}' is not declared", c.name));
+            }
+            if stored.allow_extra_fields != declared.allow_extra_fields {
+                return mismatch(format!(
+                    "table {} extra fields, the type does not",
+                    if stored.allow_extra_fields { "allows" } else { "rejects" }
+                ));
+            }
+        }
+    }
+    let declared = T::indexes();
+    if let Some(index) = declared.iter().find(|d| !table.indexes.contains(d)) {
+        return mismatch(format!("{:?} index on '{}' is not in the table", index.kind, index.field));
+    }
+    if let Some(index) = table.indexes.iter().find(|d| !declared.contains(d)) {
+        return mismatch(format!("{:?} index on '{}' is not declared", index.kind, index.field));
+    }
+    let declared = T::unique();
+    if let Some(c) = declared.iter().find(|c| !table.unique.contains(c)) {
+        return mismatch(format!("unique constraint on ({}) is not in the table", c.fields.join(", ")));
+    }
+    if let Some(c) = table.unique.iter().find(|c| !declared.contains(c)) {
+        return mismatch(format!("unique constraint on ({}) is not declared", c.fields.join(", ")));
+    }
+    Ok(())
+}
+
+/// A table whose records are read and written as `T`. Created with
+/// [`MiniDB::table`]; every call goes through the same checks, write-ahead
+/// log and transactions as the untyped methods.
+pub struct TypedTable<'a, T> {
+    db: &'a mut MiniDB,
+    name: String,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<'a, T: OxidbRecord> TypedTable<'a, T> {
+    pub(crate) fn new(db: &'a mut MiniDB, name: &str) -> Self {
+        Self { db, name: name.to_string(), _type: PhantomData }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Inserts `value` under its own id. Fails if a record with that id
+    /// already exists.
+    pub fn insert(&mut self, value: &T) -> Result<()> {
+        let record = to_record(&self.name, value)?;
+        self.db.insert(&self.name, record)
+    }
+
+    /// Inserts `value` under an id picked by the table's
+    /// [`IdStrategy`](crate::IdStrategy), ignoring its own id, and returns
+    /// the new id.
+    pub fn insert_new(&mut self, value: &T) -> Result<u64> {
+        let record = to_record(&self.name, value)?;
+        self.db.insert_new(&self.name, record.data)
+    }
+
+    pub fn get(&self, id: u64) -> Result<Option<T>> {
+        self.db.get(&self.name, id)?.map(|r| from_record(&self.name, r)).transpose()
+    }
+
+    /// Replaces the stored record with `value`. Unlike
+    /// [`MiniDB::update`], fields that `T` does not have are dropped. Fails
+    /// if there is no record with its id.
+    pub fn update(&mut self, value: &T) -> Result<()> {
+        let record = to_record(&self.name, value)?;
+        if self.db.get(&self.name, record.id)?.is_none() {
+            return Err(OxidbError::RecordNotFound { table: self.name.clone(), id: record.id });
+        }
+        self.db.upsert(&self.name, record)?;
+        Ok(())
+    }
+
+    /// Inserts `value`, replacing any record with the same id.
+    pub fn upsert(&mut self, value: &T) -> Result<()> {
+        let record = to_record(&self.name, value)?;
+        self.db.upsert(&self.name, record)?;
+        Ok(())
+    }
+
+    /// Removes the record with this id and returns it.
+    pub fn delete(&mut self, id: u64) -> Result<T> {
+        let record = self.db.delete(&self.name, id)?;
+        from_record(&self.name, &record)
+    }
+
+    /// Starts a query over the table, with the same filters and orderings
+    /// as [`MiniDB::query`].
+    pub fn query(&self) -> TypedQuery<'_, T> {
+        TypedQuery { query: self.db.query(&self.name), _type: PhantomData }
+    }
+}
+
+/// A [`Query`] whose results are converted to `T`, created with
+/// [`TypedTable::query`].
+pub struct TypedQuery<'a, T> {
+    query: Query<'a>,
+    _type: PhantomData<fn() -> T>,
+}
+
+/// One page of typed query results.
+pub struct TypedPage<T> {
+    pub records: Vec<T>,
+    /// Where the next page starts, or `None` if this was the last page.
+    pub next: Option<Cursor>,
+}
+
+impl<'a, T: OxidbRecord> TypedQuery<'a, T> {
+    /// Adds a condition; several calls are combined with AND.
+    pub fn filter(self, filter: Filter) -> Self {
+        self.map(|q| q.filter(filter))
+    }
+
+    pub fn and(self, filter: Filter) -> Self {
+        self.map(|q| q.and(filter))
+    }
+
+    pub fn or(self, filter: Filter) -> Self {
+        self.map(|q| q.or(filter))
+    }
+
+    pub fn order_by(self, key: SortKey) -> Self {
+        self.map(|q| q.order_by(key))
+    }
+
+    pub fn offset(self, offset: usize) -> Self {
+        self.map(|q| q.offset(offset))
+    }
+
+    pub fn limit(self, limit: usize) -> Self {
+        self.map(|q| q.limit(limit))
+    }
+
+    pub fn after(self, cursor: Cursor) -> Self {
+        self.map(|q| q.after(cursor))
+    }
+
+    pub fn records(&self) -> Result<Vec<T>> {
+        self.convert(self.query.records()?)
+    }
+
+    pub fn count(&self) -> Result<usize> {
+        self.query.count()
+    }
+
+    pub fn ids(&self) -> Result<Vec<u64>> {
+        self.query.ids()
+    }
+
+    /// Returns up to `size` values, plus a cursor for the next page if there
+    /// are more.
+    pub fn page(&self, size: usize) -> Result<TypedPage<T>> {
+        let page = self.query.page(size)?;
+        Ok(TypedPage { records: self.convert(page.records)?, next: page.next })
+    }
+
+    fn map(self, f: impl FnOnce(Query<'a>) -> Query<'a>) -> Self {
+        Self { query: f(self.query), _type: PhantomData }
+    }
+
+    fn convert(&self, records: Vec<&Record>) -> Result<Vec<T>> {
+        records.into_iter().map(|r| from_record(self.query.table_name(), r)).collect()
+    }
+}
+
+/// Converts `value` into a record: the id field becomes the record id and
+/// the other fields its data.
+fn to_record<T: OxidbRecord>(table: &str, value: &T) -> Result<Record> {
+    let id = value.id();
+    let error = |reason: String| OxidbError::Conversion { table: table.to_string(), id: Some(id), reason };
+    // The id is left out: it is stored as the record id, and a random one
+    // does not fit in a `Value::Int`.
+    let serializer = ValueSerializer { skip: Some(T::ID_FIELD) };
+    let mut data: HashMap<String, Value> = match value.serialize(serializer).map_err(|e| error(e.to_string()))? {
+        Value::Map(fields) => fields.into_iter().collect(),
+        _ => return Err(error("does not serialize to a map".to_string())),
+    };
+    if let Some(schema) = T::schema() {
+        restore_types(&schema, &mut data);
+    }
+    Ok(Record { id, data })
+}
+
+/// Serde has no timestamp type and serializes `Vec<u8>` as a sequence, so
+/// those arrive as an int and a list of ints; the schema says which of them
+/// they really are.
+fn restore_types(schema: &Schema, data: &mut HashMap<String, Value>) {
+    for column in &schema.columns {
+        let Some(value) = data.get_mut(&column.name) else { continue };
+        match (column.ty, &*value) {
+            (ColumnType::Timestamp, Value::Int(millis)) => *value = Value::Timestamp(*millis),
+            (ColumnType::Bytes, Value::List(items)) => {
+                let bytes: Option<Vec<u8>> = items.iter()
+                    .map(|v| v.as_i64().and_then(|b| u8::try_from(b).ok()))
+                    .collect();
+                if let Some(bytes) = bytes {
+                    *value = Value::Bytes(bytes);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Converts a record back into `T`. A stored field with the name of the id
+/// field, written through the untyped methods, is ignored in favour of the
+/// record id.
+fn from_record<T: OxidbRecord>(table: &str, record: &Record) -> Result<T> {
+    let mut data = record.data.clone();
+    data.remove(T::ID_FIELD);
+    let fields = RecordFields {
+        id: Some((T::ID_FIELD, record.id)),
+        data: data.into_iter(),
+        value: None,
+    };
+    T::deserialize(de::value::MapAccessDeserializer::new(fields)).map_err(|e: de::value::Error| {
+        OxidbError::Conversion { table: table.to_string(), id: Some(record.id), reason: e.to_string() }
+    })
+}
+
+/// The fields of a record as a serde map, starting with the id field. The id
+/// is handed out as a `u64`, since random ids do not fit in a `Value::Int`.
+struct RecordFields {
+    id: Option<(&'static str, u64)>,
+    data: std::collections::hash_map::IntoIter<String, Value>,
+    value: Option<Value>,
+}
+
+impl<'de> MapAccess<'de> for RecordFields {
+    type Error = de::value::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> std::result::Result<Option<K::Value>, Self::Error> {
+        if let Some((field, _)) = self.id {
+            return seed.deserialize(field.into_deserializer()).map(Some);
+        }
+        match self.data.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(key.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> std::result::Result<V::Value, Self::Error> {
+        if let Some((_, id)) = self.id.take() {
+            return seed.deserialize(id.into_deserializer());
+        }
+        seed.deserialize(self.value.take().unwrap_or(Value::Null))
+    }
+}
@@ -0,0 +1,974 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use serde::de::{self, Deserialize, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, Serializer};
+
+/// A single field value stored in a `Record`.
+///
+/// In the JSON table files most variants map onto their natural JSON
+/// counterpart. The ones JSON has no type for are written as a single-key
+/// object: `{"$bytes": "<base64>"}`, `{"$timestamp": <millis>}` and
+/// `{"$float": "NaN"}` for non-finite floats. A map that itself has exactly one
+/// key starting with `$` is wrapped as `{"$map": {...}}` so it can never be
+/// mistaken for one of those.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    /// Milliseconds since the Unix epoch.
+    Timestamp(i64),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    /// Name of the variant, as used in type mismatch errors.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::String(_) => "string",
+            Value::Bytes(_) => "bytes",
+            Value::Timestamp(_) => "timestamp",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+        }
+    }
+
+    /// Roughly how many bytes the value takes up in memory, including what
+    /// it owns on the heap. Used for the memory limit.
+    pub(crate) fn approx_size(&self) -> usize {
+        std::mem::size_of::<Value>()
+            + match self {
+                Value::String(s) => s.len(),
+                Value::Bytes(b) => b.len(),
+                Value::List(l) => l.iter().map(Value::approx_size).sum(),
+                Value::Map(m) => m.iter().map(|(k, v)| k.len() + v.approx_size()).sum(),
+                _ => 0,
+            }
+    }
+
+    /// Compares two values of the same kind; ints and floats compare
+    /// numerically. Values of different kinds are not comparable, and maps
+    /// only compare as equal or not comparable.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Null, Value::Null) => Some(Ordering::Equal),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Int(a), Value::Float(b)) => cmp_int_float(*a, *b),
+            (Value::Float(a), Value::Int(b)) => cmp_int_float(*b, *a).map(Ordering::reverse),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+            (Value::Bytes(a), Value::Bytes(b)) => Some(a.cmp(b)),
+            (Value::Timestamp(a), Value::Timestamp(b)) => Some(a.cmp(b)),
+            (Value::List(a), Value::List(b)) => {
+                for (x, y) in a.iter().zip(b) {
+                    match x.compare(y)? {
+                        Ordering::Equal => continue,
+                        other => return Some(other),
+                    }
+                }
+                Some(a.len().cmp(&b.len()))
+            }
+            (Value::Map(a), Value::Map(b)) if a == b => Some(Ordering::Equal),
+            _ => None,
+        }
+    }
+
+    /// A total order over all values, used for sorting. Values of different
+    /// kinds are ranked null < bool < number < string < bytes < timestamp <
+    /// list < map; ints and floats are ordered together numerically, with NaN
+    /// above every other number.
+    pub fn total_cmp(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a.cmp(b),
+            (Value::Int(a), Value::Float(b)) => cmp_int_float(*a, *b).unwrap_or(Ordering::Less),
+            (Value::Float(a), Value::Int(b)) => cmp_int_float(*b, *a).map_or(Ordering::Greater, Ordering::reverse),
+            (Value::Float(a), Value::Float(b)) => {
+                match (a.is_nan(), b.is_nan()) {
+                    (true, true) => Ordering::Equal,
+                    (true, false) => Ordering::Greater,
+                    (false, true) => Ordering::Less,
+                    (false, false) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+                }
+            }
+            (Value::List(a), Value::List(b)) => a.iter()
+                .zip(b)
+                .map(|(x, y)| x.total_cmp(y))
+                .find(|o| o.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len())),
+            (Value::Map(a), Value::Map(b)) => a.iter()
+                .zip(b)
+                .map(|((ka, va), (kb, vb))| ka.cmp(kb).then_with(|| va.total_cmp(vb)))
+                .find(|o| o.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len())),
+            _ => match self.rank().cmp(&other.rank()) {
+                Ordering::Equal => self.compare(other).unwrap_or(Ordering::Equal),
+                unequal => unequal,
+            },
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Int(_) | Value::Float(_) => 2,
+            Value::String(_) => 3,
+            Value::Bytes(_) => 4,
+            Value::Timestamp(_) => 5,
+            Value::List(_) => 6,
+            Value::Map(_) => 7,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// Floats, and ints widened to a float.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Float(f) => Some(*f),
+            Value::Int(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_timestamp(&self) -> Option<i64> {
+        match self {
+            Value::Timestamp(t) => Some(*t),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(l) => Some(l),
+            _ => None,
+        }
+    }
+
+    pub fn as_map(&self) -> Option<&BTreeMap<String, Value>> {
+        match self {
+            Value::Map(m) => Some(m),
+            _ => None,
+        }
+    }
+}
+
+/// Compares an int with a float exactly; casting the int to a float would
+/// round it above 2^53 and make the order intransitive. `None` for NaN.
+fn cmp_int_float(int: i64, float: f64) -> Option<Ordering> {
+    if float.is_nan() {
+        return None;
+    }
+    // The cast saturates for floats beyond the i128 range, which still
+    // orders them correctly against every i64.
+    let whole = float.trunc();
+    Some((int as i128).cmp(&(whole as i128)).then(whole.partial_cmp(&float)?))
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Self {
+        Value::Bool(v)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Self {
+        Value::Int(v)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(v: i32) -> Self {
+        Value::Int(v as i64)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(v: u32) -> Self {
+        Value::Int(v as i64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Value::Float(v)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Self {
+        Value::String(v.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(v: String) -> Self {
+        Value::String(v)
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(v: Vec<u8>) -> Self {
+        Value::Bytes(v)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(v: Vec<Value>) -> Self {
+        Value::List(v)
+    }
+}
+
+impl From<BTreeMap<String, Value>> for Value {
+    fn from(v: BTreeMap<String, Value>) -> Self {
+        Value::Map(v)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(Value::Null, Into::into)
+    }
+}
+
+impl PartialEq<str> for Value {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == Some(other)
+    }
+}
+
+impl PartialEq<&str> for Value {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == Some(*other)
+    }
+}
+
+impl PartialEq<i64> for Value {
+    fn eq(&self, other: &i64) -> bool {
+        self.as_i64() == Some(*other)
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Null => serializer.serialize_unit(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Int(i) => serializer.serialize_i64(*i),
+            // Binary formats store non-finite floats and raw bytes natively.
+            Value::Float(f) if f.is_finite() || !serializer.is_human_readable() => serializer.serialize_f64(*f),
+            Value::Float(f) => {
+                let name = if f.is_nan() { "NaN" } else if *f > 0.0 { "inf" } else { "-inf" };
+                tagged(serializer, "$float", name)
+            }
+            Value::String(s) => serializer.serialize_str(s),
+            Value::Bytes(b) if !serializer.is_human_readable() => serializer.serialize_bytes(b),
+            Value::Bytes(b) => tagged(serializer, "$bytes", &base64_encode(b)),
+            Value::Timestamp(t) => tagged(serializer, "$timestamp", t),
+            Value::List(l) => serializer.collect_seq(l),
+            Value::Map(m) if m.len() == 1 && m.keys().all(|k| k.starts_with('$')) => {
+                tagged(serializer, "$map", m)
+            }
+            Value::Map(m) => serializer.collect_map(m),
+        }
+    }
+}
+
+fn tagged<S: Serializer, T: Serialize + ?Sized>(serializer: S, tag: &str, value: &T) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(1))?;
+    map.serialize_entry(tag, value)?;
+    map.end()
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor { unwrap_tags: true })
+    }
+}
+
+/// Deserializes a value; with `unwrap_tags` off, an object at the top level is
+/// read as a plain map even if it looks like a `$` tag. That is how the
+/// contents of a `{"$map": ...}` wrapper are read.
+struct ValueVisitor {
+    unwrap_tags: bool,
+}
+
+impl<'de> DeserializeSeed<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a record value")
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Value, E> {
+        Ok(Value::Int(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Value, E> {
+        i64::try_from(v).map(Value::Int).map_err(|_| E::custom(format!("integer {} does not fit in an i64", v)))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Value, E> {
+        Ok(Value::Float(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Value, E> {
+        Ok(Value::Bytes(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Value, E> {
+        Ok(Value::Bytes(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut list = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            list.push(item);
+        }
+        Ok(Value::List(list))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Value, A::Error> {
+        let mut map = BTreeMap::new();
+        while let Some(key) = access.next_key::<String>()? {
+            let value = access.next_value_seed(ValueVisitor { unwrap_tags: key != "$map" })?;
+            map.insert(key, value);
+        }
+        if !self.unwrap_tags || map.len() != 1 {
+            // A "$map" entry was read without unwrapping; now that we know it
+            // is not a wrapper, give it the treatment every other value got.
+            if let Some(Value::Map(inner)) = map.remove("$map") {
+                map.insert("$map".to_string(), unwrap_tag(inner)?);
+            }
+            return Ok(Value::Map(map));
+        }
+        match map.pop_first().expect("map has one entry") {
+            (key, Value::Map(inner)) if key == "$map" => Ok(Value::Map(inner)),
+            (key, value) => unwrap_tag(BTreeMap::from([(key, value)])),
+        }
+    }
+}
+
+/// Reads a value into any `Deserialize` type, which is how typed tables turn
+/// record data back into structs. Timestamps read as their milliseconds and
+/// bytes as either a byte buffer or a sequence of `u8`.
+impl<'de> Deserializer<'de> for Value {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            Value::Null => visitor.visit_unit(),
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::Int(i) | Value::Timestamp(i) => visitor.visit_i64(i),
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::String(s) => visitor.visit_string(s),
+            Value::Bytes(b) => visitor.visit_byte_buf(b),
+            Value::List(l) => visitor.visit_seq(de::value::SeqDeserializer::new(l.into_iter())),
+            Value::Map(m) => visitor.visit_map(de::value::MapDeserializer::new(m.into_iter())),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            Value::Null => visitor.visit_none(),
+            other => visitor.visit_some(other),
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            Value::Bytes(b) => visitor.visit_seq(de::value::SeqDeserializer::new(b.into_iter())),
+            other => other.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self {
+            Value::String(variant) => visitor.visit_enum(de::IntoDeserializer::into_deserializer(variant)),
+            Value::Map(m) if m.len() == 1 => {
+                visitor.visit_enum(de::value::MapAccessDeserializer::new(de::value::MapDeserializer::new(m.into_iter())))
+            }
+            other => Err(de::Error::invalid_type(de::Unexpected::Other(other.type_name()), &"an enum")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de> de::IntoDeserializer<'de, de::value::Error> for Value {
+    type Deserializer = Value;
+
+    fn into_deserializer(self) -> Value {
+        self
+    }
+}
+
+/// Writes any `Serialize` type as a value, which is how typed tables turn
+/// structs into record data. It maps serde's data model onto values one to
+/// one: floats stay floats even when they are not finite, a `$` key is never
+/// read as a tag, and unsigned integers that do not fit in an `i64` are an
+/// error. Serde has no timestamp type, and `Vec<u8>` serializes as a
+/// sequence, so those come out as an int and a list of ints.
+#[derive(Default)]
+pub(crate) struct ValueSerializer {
+    /// A field of the outer struct or map to leave out.
+    pub(crate) skip: Option<&'static str>,
+}
+
+type SerResult = Result<Value, de::value::Error>;
+
+fn int<T: TryInto<i64> + fmt::Display + Copy>(v: T) -> SerResult {
+    v.try_into().map(Value::Int).map_err(|_| ser::Error::custom(format!("{} does not fit in an i64", v)))
+}
+
+fn variant(name: &'static str, value: Value) -> Value {
+    Value::Map(BTreeMap::from([(name.to_string(), value)]))
+}
+
+impl Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = de::value::Error;
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeList;
+    type SerializeMap = SerializeFields;
+    type SerializeStruct = SerializeFields;
+    type SerializeStructVariant = SerializeFields;
+
+    fn serialize_bool(self, v: bool) -> SerResult {
+        Ok(Value::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> SerResult {
+        int(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> SerResult {
+        int(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> SerResult {
+        int(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> SerResult {
+        int(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> SerResult {
+        int(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> SerResult {
+        int(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> SerResult {
+        int(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> SerResult {
+        int(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> SerResult {
+        int(v)
+    }
+
+    fn serialize_u128(self, v: u128) -> SerResult {
+        int(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> SerResult {
+        Ok(Value::Float(v.into()))
+    }
+
+    fn serialize_f64(self, v: f64) -> SerResult {
+        Ok(Value::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> SerResult {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> SerResult {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> SerResult {
+        Ok(Value::Bytes(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> SerResult {
+        Ok(Value::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> SerResult {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> SerResult {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> SerResult {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> SerResult {
+        Ok(Value::String(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> SerResult {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        name: &'static str,
+        value: &T,
+    ) -> SerResult {
+        Ok(variant(name, value.serialize(ValueSerializer::default())?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeList, Self::Error> {
+        Ok(SerializeList { items: Vec::with_capacity(len.unwrap_or(0)), variant: None })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeList, Self::Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeList, Self::Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeList, Self::Error> {
+        Ok(SerializeList { items: Vec::with_capacity(len), variant: Some(variant) })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeFields, Self::Error> {
+        Ok(SerializeFields { fields: BTreeMap::new(), key: None, skip: self.skip, variant: None })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeFields, Self::Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeFields, Self::Error> {
+        Ok(SerializeFields { fields: BTreeMap::new(), key: None, skip: None, variant: Some(variant) })
+    }
+}
+
+pub(crate) struct SerializeList {
+    items: Vec<Value>,
+    variant: Option<&'static str>,
+}
+
+impl SerializeList {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), de::value::Error> {
+        self.items.push(value.serialize(ValueSerializer::default())?);
+        Ok(())
+    }
+
+    fn finish(self) -> SerResult {
+        let list = Value::List(self.items);
+        Ok(match self.variant {
+            Some(name) => variant(name, list),
+            None => list,
+        })
+    }
+}
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = Value;
+    type Error = de::value::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> SerResult {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = Value;
+    type Error = de::value::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> SerResult {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = Value;
+    type Error = de::value::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> SerResult {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeList {
+    type Ok = Value;
+    type Error = de::value::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> SerResult {
+        self.finish()
+    }
+}
+
+pub(crate) struct SerializeFields {
+    fields: BTreeMap<String, Value>,
+    key: Option<String>,
+    skip: Option<&'static str>,
+    variant: Option<&'static str>,
+}
+
+impl SerializeFields {
+    fn insert<T: Serialize + ?Sized>(&mut self, key: String, value: &T) -> Result<(), de::value::Error> {
+        if self.skip != Some(key.as_str()) {
+            self.fields.insert(key, value.serialize(ValueSerializer::default())?);
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> SerResult {
+        let map = Value::Map(self.fields);
+        Ok(match self.variant {
+            Some(name) => variant(name, map),
+            None => map,
+        })
+    }
+}
+
+impl ser::SerializeMap for SerializeFields {
+    type Ok = Value;
+    type Error = de::value::Error;
+
+    /// Keys must be strings; integer keys are written out in decimal, like
+    /// JSON does.
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Self::Error> {
+        self.key = Some(match key.serialize(ValueSerializer::default())? {
+            Value::String(key) => key,
+            Value::Int(key) => key.to_string(),
+            other => return Err(ser::Error::custom(format!("map keys must be strings, not {}", other.type_name()))),
+        });
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        let key = self.key.take().ok_or_else(|| ser::Error::custom("map value without a key"))?;
+        self.insert(key, value)
+    }
+
+    fn end(self) -> SerResult {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for SerializeFields {
+    type Ok = Value;
+    type Error = de::value::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error> {
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(self) -> SerResult {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeFields {
+    type Ok = Value;
+    type Error = de::value::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error> {
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(self) -> SerResult {
+        self.finish()
+    }
+}
+
+/// Turns a single-key `$` object into the value it encodes; any other map is
+/// returned as is.
+fn unwrap_tag<E: de::Error>(mut map: BTreeMap<String, Value>) -> Result<Value, E> {
+    if map.len() != 1 {
+        return Ok(Value::Map(map));
+    }
+    let (key, value) = map.pop_first().expect("map has one entry");
+    match (key.as_str(), value) {
+        ("$bytes", Value::String(s)) => base64_decode(&s)
+            .map(Value::Bytes)
+            .ok_or_else(|| E::custom("invalid base64 in $bytes")),
+        ("$timestamp", Value::Int(t)) => Ok(Value::Timestamp(t)),
+        ("$float", Value::String(s)) => match s.as_str() {
+            "NaN" => Ok(Value::Float(f64::NAN)),
+            "inf" => Ok(Value::Float(f64::INFINITY)),
+            "-inf" => Ok(Value::Float(f64::NEG_INFINITY)),
+            other => Err(E::custom(format!("invalid $float '{}'", other))),
+        },
+        ("$map", Value::Map(inner)) => Ok(Value::Map(inner)),
+        ("$bytes" | "$timestamp" | "$float" | "$map", other) => {
+            Err(E::custom(format!("unexpected {} for tag '{}'", other.type_name(), key)))
+        }
+        (_, value) => Ok(Value::Map(BTreeMap::from([(key, value)]))),
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim_end_matches('=');
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let mut buf = 0u32;
+    let mut bits = 0;
+    for c in s.bytes() {
+        let v = BASE64.iter().position(|&b| b == c)? as u32;
+        buf = buf << 6 | v;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buf >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values_round_trip_through_json() {
+        let mut nested = BTreeMap::new();
+        nested.insert("$weird".to_string(), Value::Int(1));
+        let mut lookalike = BTreeMap::new();
+        lookalike.insert("$map".to_string(), Value::Map(nested.clone()));
+        let mut map = BTreeMap::new();
+        map.insert("escaped".to_string(), Value::Map(nested));
+        map.insert("lookalike".to_string(), Value::Map(lookalike.clone()));
+        lookalike.insert("other".to_string(), Value::Bytes(vec![1]));
+        map.insert("$map".to_string(), Value::Map(lookalike));
+        map.insert("list".to_string(), Value::List(vec![Value::Null, Value::Bool(true)]));
+
+        let values = vec![
+            Value::Null,
+            Value::Bool(false),
+            Value::Int(-42),
+            Value::Float(1.0),
+            Value::Float(f64::INFINITY),
+            Value::String("Stan".into()),
+            Value::Bytes(vec![0, 1, 2, 254, 255]),
+            Value::Bytes(vec![]),
+            Value::Timestamp(1_700_000_000_000),
+            Value::Map(map),
+        ];
+        for value in values {
+            let json = serde_json::to_string(&value).unwrap();
+            let back: Value = serde_json::from_str(&json).unwrap();
+            assert_eq!(back, value, "round trip through {}", json);
+        }
+
+        let nan: Value = serde_json::from_str(&serde_json::to_string(&Value::Float(f64::NAN)).unwrap()).unwrap();
+        assert!(nan.as_f64().unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_total_order() {
+        let mut values = [
+            Value::from("b"),
+            Value::Float(f64::NAN),
+            Value::Int(2),
+            Value::Null,
+            Value::Float(1.5),
+            Value::Bool(true),
+            Value::from("a"),
+            Value::Int(-7),
+        ];
+        values.sort_by(Value::total_cmp);
+        assert_eq!(values[0], Value::Null);
+        assert_eq!(values[1], Value::Bool(true));
+        assert_eq!(&values[2..5], &[Value::Int(-7), Value::Float(1.5), Value::Int(2)]);
+        assert!(values[5].as_f64().unwrap().is_nan());
+        assert_eq!(&values[6..], &[Value::from("a"), Value::from("b")]);
+
+        // Ints en floats boven 2^53 worden exact vergeleken, dus transitief
+        let big = 1i64 << 53;
+        let (a, b, c) = (Value::Int(big), Value::Float(big as f64 + 2.0), Value::Int(big + 1));
+        assert_eq!(a.total_cmp(&b), Ordering::Less);
+        assert_eq!(c.total_cmp(&b), Ordering::Less);
+        assert_eq!(a.total_cmp(&c), Ordering::Less);
+        assert_eq!(Value::Int(big + 1).compare(&Value::Float(big as f64)), Some(Ordering::Greater));
+        assert_eq!(Value::Int(i64::MAX).total_cmp(&Value::Float(i64::MAX as f64)), Ordering::Less);
+        assert_eq!(Value::Float(-0.5).total_cmp(&Value::Int(0)), Ordering::Less);
+        assert_eq!(Value::Float(-0.0).total_cmp(&Value::Int(0)), Ordering::Equal);
+        assert_eq!(Value::Float(f64::NEG_INFINITY).total_cmp(&Value::Int(i64::MIN)), Ordering::Less);
+        assert_eq!(Value::Float(f64::NAN).compare(&Value::Int(1)), None);
+    }
+
+    #[test]
+    fn test_base64() {
+        for input in [&b""[..], b"f", b"fo", b"foo", b"foob", b"fooba", b"foobar"] {
+            assert_eq!(base64_decode(&base64_encode(input)).unwrap(), input);
+        }
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+    }
+
+    #[test]
+    fn test_serialize_into_value() {
+        #[derive(serde::Serialize)]
+        enum Shape {
+            Dot,
+            Circle(f64),
+            Rect { w: i32, h: i32 },
+        }
+        fn to_value<T: Serialize>(value: &T) -> Result<Value, de::value::Error> {
+            value.serialize(ValueSerializer::default())
+        }
+        assert_eq!(to_value(&f64::INFINITY).unwrap(), Value::Float(f64::INFINITY));
+        assert_eq!(to_value(&Some(42u8)).unwrap(), Value::Int(42));
+        assert!(to_value(&u64::MAX).is_err());
+        assert_eq!(to_value(&Shape::Dot).unwrap(), Value::from("Dot"));
+        assert_eq!(to_value(&Shape::Circle(1.5)).unwrap(), Value::Map(BTreeMap::from([("Circle".into(), Value::Float(1.5))])));
+        let rect = BTreeMap::from([("w".to_string(), Value::Int(2)), ("h".to_string(), Value::Int(3))]);
+        assert_eq!(to_value(&Shape::Rect { w: 2, h: 3 }).unwrap(), Value::Map(BTreeMap::from([("Rect".into(), Value::Map(rect))])));
+        // Een `$`-sleutel blijft gewoon een sleutel
+        let tagged = std::collections::HashMap::from([("$timestamp", 5)]);
+        assert_eq!(to_value(&tagged).unwrap(), Value::Map(BTreeMap::from([("$timestamp".into(), Value::Int(5))])));
+    }
+}
@@ -0,0 +1,333 @@
+use std::borrow::Cow;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+use crate::checksum::crc32;
+use crate::crypto::{KeyError, Keyring};
+use crate::error::{OxidbError, Result};
+use crate::format::Compression;
+use crate::index::{IndexDef, UniqueConstraint};
+use crate::storage;
+use crate::table::{Record, Table};
+
+/// Name of the write-ahead log inside the database directory.
+pub(crate) const WAL_FILE: &str = "oxidb.wal";
+
+/// Name of the file holding the log's epoch: how many times the log has been
+/// emptied. Readers next to a writer use it to notice a checkpoint.
+pub(crate) const EPOCH_FILE: &str = "oxidb.epoch";
+
+/// First byte of an encrypted frame payload. Plain payloads are JSON objects
+/// and start with `{`.
+const ENCRYPTED_FRAME: u8 = 0x01;
+const FRAME_AAD: &[u8] = b"oxidb wal frame";
+
+/// When appends to the write-ahead log are flushed to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncPolicy {
+    /// fsync after every write: nothing acknowledged is ever lost.
+    #[default]
+    Always,
+    /// fsync on the first append after the interval has elapsed since the
+    /// last fsync, and when the database is dropped. Nothing flushes in the
+    /// background: a crash loses the writes since the last fsync, which after
+    /// a quiet spell can be more than one interval's worth.
+    EveryMillis(u64),
+    /// Leave flushing to the operating system. Survives a process crash, not
+    /// a power failure.
+    Never,
+}
+
+/// One change to the database, as recorded in the write-ahead log. Record
+/// changes are logged as the complete record after the change, so replaying
+/// an entry twice has the same effect as replaying it once.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub(crate) enum WalEntry {
+    CreateTable { table: Box<Table> },
+    DropTable { table: String },
+    RenameTable { from: String, to: String },
+    TruncateTable { table: String },
+    Put { table: String, record: Record },
+    Delete { table: String, id: u64 },
+    CreateIndex { table: String, index: IndexDef },
+    DropIndex { table: String, field: String },
+    AddUnique { table: String, constraint: UniqueConstraint },
+    DropUnique { table: String, fields: Vec<String> },
+    SetCompression { table: String, compression: Option<Compression> },
+    /// The changes of a committed transaction, logged as one frame so that
+    /// replay applies either all of them or none.
+    Batch { entries: Vec<WalEntry> },
+}
+
+impl WalEntry {
+    /// Names of the tables the entry changes.
+    pub(crate) fn tables(&self) -> Vec<&str> {
+        match self {
+            WalEntry::CreateTable { table } => vec![&table.name],
+            WalEntry::RenameTable { from, to } => vec![from, to],
+            WalEntry::DropTable { table }
+            | WalEntry::TruncateTable { table }
+            | WalEntry::Put { table, .. }
+            | WalEntry::Delete { table, .. }
+            | WalEntry::CreateIndex { table, .. }
+            | WalEntry::DropIndex { table, .. }
+            | WalEntry::AddUnique { table, .. }
+            | WalEntry::DropUnique { table, .. }
+            | WalEntry::SetCompression { table, .. } => vec![table],
+            WalEntry::Batch { entries } => entries.iter().flat_map(WalEntry::tables).collect(),
+        }
+    }
+}
+
+/// The append-only log of changes made since the last checkpoint.
+///
+/// Each entry is written as a frame: the payload length and its CRC-32, both
+/// little-endian `u32`s, followed by the JSON-encoded entry. A frame that is
+/// cut short or fails its checksum marks the end of the log; that is what a
+/// crash in the middle of an append leaves behind. An intact frame that does
+/// not decode is never skipped, since the entries after it were acknowledged.
+/// With an encryption key the JSON is encrypted, so each frame is sealed on
+/// its own.
+///
+/// The log of an in-memory database has no file: appends are dropped and
+/// there is never anything to replay.
+pub(crate) struct Wal {
+    path: Option<PathBuf>,
+    keys: Keyring,
+    file: Option<File>,
+    policy: SyncPolicy,
+    last_sync: Instant,
+    unsynced: bool,
+    // A failed append left bytes behind that could not be cut off again.
+    // Anything appended after them would be lost on replay, so appends are
+    // refused until the next replay cleans up.
+    torn: bool,
+    // Makes the next append fail after writing this many bytes of its frame.
+    #[cfg(test)]
+    fail_after: Option<usize>,
+}
+
+impl Wal {
+    pub(crate) fn new(dir: Option<&Path>) -> Self {
+        Self {
+            path: dir.map(|dir| dir.join(WAL_FILE)),
+            keys: Keyring::default(),
+            file: None,
+            policy: SyncPolicy::default(),
+            last_sync: Instant::now(),
+            unsynced: false,
+            torn: false,
+            #[cfg(test)]
+            fail_after: None,
+        }
+    }
+
+    pub(crate) fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub(crate) fn set_keys(&mut self, keys: Keyring) {
+        self.keys = keys;
+    }
+
+    pub(crate) fn set_policy(&mut self, policy: SyncPolicy) {
+        self.policy = policy;
+    }
+
+    pub(crate) fn policy(&self) -> SyncPolicy {
+        self.policy
+    }
+
+    /// Whether appends have been written since the last fsync.
+    #[cfg(test)]
+    pub(crate) fn has_unsynced(&self) -> bool {
+        self.unsynced
+    }
+
+    #[cfg(test)]
+    pub(crate) fn fail_next_append_after(&mut self, bytes: usize) {
+        self.fail_after = Some(bytes);
+    }
+
+    /// Appends a frame, and syncs if the policy says so. If either fails the
+    /// file is cut back to where it was, so a frame cut short never hides the
+    /// frames appended after it.
+    pub(crate) fn append(&mut self, entry: &WalEntry) -> Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+        if self.torn {
+            let e = std::io::Error::other("an earlier failed append could not be undone; reload the database");
+            return Err(OxidbError::io(path, e));
+        }
+        let payload = serde_json::to_vec(entry).map_err(|e| OxidbError::Serde {
+            path: path.clone(),
+            table: None,
+            source: e.into(),
+        })?;
+        let payload = match self.keys.seal(FRAME_AAD, &payload) {
+            Some(sealed) => [&[ENCRYPTED_FRAME][..], &sealed].concat(),
+            None => payload,
+        };
+        let mut frame = Vec::with_capacity(payload.len() + 8);
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&crc32(&payload).to_le_bytes());
+        frame.extend_from_slice(&payload);
+
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| OxidbError::io(path, e))?;
+                self.file.insert(file)
+            }
+        };
+        let start = file.metadata().map_err(|e| OxidbError::io(path, e))?.len();
+        let due = match self.policy {
+            SyncPolicy::Always => true,
+            SyncPolicy::EveryMillis(ms) => self.last_sync.elapsed() >= Duration::from_millis(ms),
+            SyncPolicy::Never => false,
+        };
+        #[cfg(test)]
+        let written = match self.fail_after.take() {
+            Some(n) => file.write_all(&frame[..n]).and_then(|_| Err(std::io::Error::other("injected failure"))),
+            None => file.write_all(&frame),
+        };
+        #[cfg(not(test))]
+        let written = file.write_all(&frame);
+        if let Err(e) = written.and_then(|_| if due { file.sync_data() } else { Ok(()) }) {
+            if file.set_len(start).and_then(|_| file.sync_data()).is_err() {
+                self.file = None;
+                self.torn = true;
+            }
+            return Err(OxidbError::io(path, e));
+        }
+        if due {
+            self.last_sync = Instant::now();
+        }
+        self.unsynced = !due;
+        Ok(())
+    }
+
+    pub(crate) fn sync(&mut self) -> Result<()> {
+        if let (Some(file), Some(path), true) = (&self.file, &self.path, self.unsynced) {
+            file.sync_data().map_err(|e| OxidbError::io(path, e))?;
+        }
+        self.unsynced = false;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Reads all intact entries. A torn frame at the end is cut off the file
+    /// so later appends do not end up behind it.
+    pub(crate) fn replay(&mut self) -> Result<Vec<WalEntry>> {
+        let (entries, torn_at) = self.read()?;
+        if let (Some(len), Some(path)) = (torn_at, &self.path) {
+            self.file = None;
+            let file = OpenOptions::new().write(true).open(path).map_err(|e| OxidbError::io(path, e))?;
+            file.set_len(len).and_then(|_| file.sync_all()).map_err(|e| OxidbError::io(path, e))?;
+        }
+        self.torn = false;
+        Ok(entries)
+    }
+
+    /// Reads all intact entries without changing the file, along with the
+    /// length of the intact part if a torn frame follows it. Only a frame that
+    /// is cut short or fails its checksum counts as torn; an intact frame
+    /// that does not decrypt or decode is an error.
+    pub(crate) fn read(&self) -> Result<(Vec<WalEntry>, Option<u64>)> {
+        self.decode(&self.read_bytes()?)
+    }
+
+    /// The raw contents of the log file; empty if there is none.
+    pub(crate) fn read_bytes(&self) -> Result<Vec<u8>> {
+        let Some(path) = &self.path else { return Ok(Vec::new()) };
+        match fs::read(path) {
+            Ok(bytes) => Ok(bytes),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(OxidbError::io(path, e)),
+        }
+    }
+
+    /// Decodes log contents obtained from [`Wal::read_bytes`], see [`Wal::read`].
+    pub(crate) fn decode(&self, bytes: &[u8]) -> Result<(Vec<WalEntry>, Option<u64>)> {
+        let Some(path) = &self.path else { return Ok((Vec::new(), None)) };
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while let Some(frame) = next_frame(&bytes[offset..]) {
+            let payload = match frame.split_first() {
+                Some((&ENCRYPTED_FRAME, sealed)) => match self.keys.open(FRAME_AAD, sealed) {
+                    Ok((plain, _)) => Cow::Owned(plain),
+                    Err(KeyError::Missing) => return Err(OxidbError::MissingKey { path: path.clone() }),
+                    Err(KeyError::Wrong) => return Err(OxidbError::WrongKey { path: path.clone() }),
+                },
+                _ => Cow::Borrowed(frame),
+            };
+            // The frame made it to disk whole, so an entry that does not
+            // decode is not a torn write. Stop here rather than let replay
+            // cut off the entries behind it.
+            match serde_json::from_slice(&payload) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => {
+                    return Err(OxidbError::Serde {
+                        path: path.clone(),
+                        table: None,
+                        source: format!("entry at byte {}: {}", offset, e).into(),
+                    });
+                }
+            }
+            offset += frame.len() + 8;
+        }
+        Ok((entries, (offset < bytes.len()).then_some(offset as u64)))
+    }
+
+    /// Empties the log, once everything in it is safely in the table files.
+    /// The epoch goes up first, so a reader that saw the old epoch after
+    /// reading the tables also read the log before it was emptied.
+    pub(crate) fn truncate(&mut self) -> Result<()> {
+        self.file = None;
+        self.unsynced = false;
+        self.torn = false;
+        let Some(path) = &self.path else { return Ok(()) };
+        if path.exists() {
+            let epoch = self.epoch()? + 1;
+            storage::write_atomic(&path.with_file_name(EPOCH_FILE), &epoch.to_le_bytes())?;
+        }
+        storage::remove_durably(path)
+    }
+
+    /// How many times the log has been emptied; 0 before the first time.
+    pub(crate) fn epoch(&self) -> Result<u64> {
+        let Some(path) = &self.path else { return Ok(0) };
+        let path = path.with_file_name(EPOCH_FILE);
+        match fs::read(&path) {
+            Ok(bytes) => match <[u8; 8]>::try_from(bytes.as_slice()) {
+                Ok(bytes) => Ok(u64::from_le_bytes(bytes)),
+                Err(_) => Err(OxidbError::io(&path, std::io::Error::new(ErrorKind::InvalidData, "epoch file is not 8 bytes"))),
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(OxidbError::io(&path, e)),
+        }
+    }
+}
+
+impl Drop for Wal {
+    fn drop(&mut self) {
+        if self.policy != SyncPolicy::Never {
+            let _ = self.sync();
+        }
+    }
+}
+
+/// The payload of the frame at the start of `bytes`, if it is complete and
+/// intact.
+fn next_frame(bytes: &[u8]) -> Option<&[u8]> {
+    let len = u32::from_le_bytes(bytes.get(0..4)?.try_into().ok()?) as usize;
+    let crc = u32::from_le_bytes(bytes.get(4..8)?.try_into().ok()?);
+    let payload = bytes.get(8..8 + len)?;
+    (crc32(payload) == crc).then_some(payload)
+}